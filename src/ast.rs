@@ -0,0 +1,568 @@
+use crate::{
+    error::Span,
+    session::{CONSTANTS, IMAGINARY_UNIT},
+    token::{Associate, MathFunction, Number, Operator, Token},
+};
+use num_bigint::BigInt;
+use std::{borrow::Cow, collections::BTreeSet, fmt::Display};
+
+/// The function called by an [`Expr::Call`]
+///
+#[derive(Debug, PartialEq, Clone)]
+pub enum Callee {
+    /// A built-in [`MathFunction`] (sin, cos, max...)
+    Math(MathFunction),
+    /// A function defined or registered in a [`crate::session::Session`]
+    User(String),
+}
+
+/// The [`Expr`] enum. The abstract syntax tree of a math expression, as built by
+/// [`crate::parser::Parser::parse_expr`].
+///
+/// Every node carries the [Span] of its token in the source expression (the number,
+/// the variable, the operator or the name of the function), so the errors raised while
+/// evaluating it can be reported at the right place
+///
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    /// A literal number ('42', '6.6')
+    Number {
+        /// the value of the literal
+        value: Number,
+        /// span of the literal
+        span: Span,
+    },
+    /// A variable or a constant ('x', 'pi')
+    Variable {
+        /// the name of the variable
+        name: String,
+        /// span of the variable
+        span: Span,
+    },
+    /// An unary operator: the negation ('-x'), the factorial ('x!') or the logical not ('not x')
+    Unary {
+        /// either [`Operator::Une`], [`Operator::Fac`] or [`Operator::Not`]
+        op: Operator,
+        /// the operand
+        operand: Box<Expr>,
+        /// span of the operator
+        span: Span,
+    },
+    /// A binary operator ('a + b', 'a ^ b')
+    Binary {
+        /// the operator
+        op: Operator,
+        /// the left operand
+        left: Box<Expr>,
+        /// the right operand
+        right: Box<Expr>,
+        /// span of the operator
+        span: Span,
+    },
+    /// A function call ('max(1, 2)', 'hyp(a, b)')
+    Call {
+        /// the called function
+        function: Callee,
+        /// the arguments, in order
+        args: Vec<Expr>,
+        /// span of the name of the function
+        span: Span,
+    },
+    /// An assignment to a variable ('x = 1')
+    Assign {
+        /// the name of the assigned variable
+        name: String,
+        /// span of the assigned variable
+        target: Span,
+        /// the assigned expression
+        value: Box<Expr>,
+        /// span of the '=' operator
+        span: Span,
+    },
+    /// Expressions chained with semicolons ('x = 2; x ^ 2'): the last one is the result
+    Sequence(Vec<Expr>),
+}
+
+/// The names an [`Expr`] depends on, found by [`Expr::dependencies`]. All the names are lowercase
+///
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Dependencies {
+    /// The free variables: the ones read before being assigned in the expression,
+    /// which must be defined in the session
+    pub variables: BTreeSet<String>,
+    /// The built-in constants read by the expression (pi, e, tau..., and i in complex mode)
+    pub constants: BTreeSet<String>,
+    /// The variables assigned by the expression with '='
+    pub assigned: BTreeSet<String>,
+    /// The functions called by the expression, both built-in and user-defined
+    pub functions: BTreeSet<String>,
+}
+
+impl Expr {
+    /// Returns the [Span] of the token of the node. A [`Expr::Sequence`] spans all its expressions
+    ///
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number { span, .. }
+            | Expr::Variable { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Assign { span, .. } => *span,
+            Expr::Sequence(_) => {
+                let mut extent = Extent(None);
+                extent.visit_expr(self);
+                extent.0.unwrap_or_default()
+            }
+        }
+    }
+
+    /// Finds out the variables, the constants and the functions the expression depends on,
+    /// and the variables it assigns. A variable assigned before being read isn't free
+    /// ('x = 2; x * y' depends on y only). The variables read by the bodies of the called
+    /// user functions are not included
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::parser::Parser;
+    ///
+    ///      let deps = Parser::parse_expr("area = pi * r^2; max(area, min_area)").unwrap().dependencies();
+    ///      assert_eq!(deps.variables.into_iter().collect::<Vec<_>>(), ["min_area", "r"]);
+    ///      assert_eq!(deps.constants.into_iter().collect::<Vec<_>>(), ["pi"]);
+    ///      assert_eq!(deps.assigned.into_iter().collect::<Vec<_>>(), ["area"]);
+    ///      assert_eq!(deps.functions.into_iter().collect::<Vec<_>>(), ["max"]);
+    ///  ```
+    ///
+    #[must_use]
+    pub fn dependencies(&self) -> Dependencies {
+        let mut dependencies = Dependencies::default();
+        dependencies.visit_expr(self);
+        dependencies
+    }
+
+    /// Same as [`Expr::dependencies`], but the imaginary unit 'i' is a constant if `complex` is true,
+    /// as it's defined by the sessions in complex mode
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::parser::Parser;
+    ///
+    ///      let expr = Parser::parse_expr("2 + 3i").unwrap();
+    ///      assert!(expr.dependencies().variables.contains("i"));
+    ///      assert!(expr.dependencies_with(true).variables.is_empty());
+    ///      assert!(expr.dependencies_with(true).constants.contains("i"));
+    ///  ```
+    ///
+    #[must_use]
+    pub fn dependencies_with(&self, complex: bool) -> Dependencies {
+        let mut dependencies = self.dependencies();
+        if complex && dependencies.variables.remove(IMAGINARY_UNIT) {
+            dependencies.constants.insert(IMAGINARY_UNIT.to_string());
+        }
+        dependencies
+    }
+
+    /// Converts the tree to Reverse Polish Notation, the form evaluated by the
+    /// [`crate::rpn_resolver::RpnResolver`]
+    ///
+    /// Example
+    /// ``
+    ///     "3 * 4 + 5 * 6" becomes "3 4 * 5 6 * +"
+    /// ``
+    #[must_use]
+    pub fn to_rpn(&self) -> Vec<(Token<'static>, Span)> {
+        let mut postfix = Postfix(Vec::new());
+        postfix.visit_expr(self);
+        postfix.0
+    }
+
+    /// The priority of the node, to decide where brackets are needed when it's displayed
+    ///
+    fn priority(&self) -> (u8, Associate) {
+        match self {
+            Expr::Unary { op, .. } | Expr::Binary { op, .. } => {
+                Token::operator_priority(&Token::Operator(*op))
+            }
+            Expr::Assign { .. } => Token::operator_priority(&Token::Operator(Operator::Eql)),
+            Expr::Sequence(_) => (0, Associate::LeftAssociative),
+            Expr::Number { value, .. } if *value < Number::NaturalNumber(BigInt::ZERO) => {
+                (0, Associate::LeftAssociative)
+            }
+            _ => (u8::MAX, Associate::LeftAssociative),
+        }
+    }
+}
+
+/// A [`Visitor`] walks an [`Expr`] tree without changing it. By default every node is visited
+/// after its parent, and its children in order of evaluation: override
+/// [`Visitor::visit_expr`] for the nodes of interest and call [`walk`] to go on with the children.
+///
+/// # Examples
+///
+/// ```
+/// #    use yarer::{ast::{walk, Expr, Visitor}, parser::Parser};
+///
+///      struct Variables(Vec<String>);
+///
+///      impl Visitor for Variables {
+///          fn visit_expr(&mut self, expr: &Expr) {
+///              if let Expr::Variable { name, .. } = expr {
+///                  self.0.push(name.clone());
+///              }
+///              walk(self, expr);
+///          }
+///      }
+///
+///      let mut variables = Variables(Vec::new());
+///      variables.visit_expr(&Parser::parse_expr("x * (y + x)").unwrap());
+///      assert_eq!(variables.0, vec!["x", "y", "x"]);
+///  ```
+///
+pub trait Visitor {
+    /// Visits a node of the tree, and then its children
+    ///
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk(self, expr);
+    }
+}
+
+/// Visits the children of a node with a [`Visitor`]
+///
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Number { .. } | Expr::Variable { .. } => (),
+        Expr::Unary { operand, .. } => visitor.visit_expr(operand),
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Call { args, .. } => args.iter().for_each(|a| visitor.visit_expr(a)),
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Sequence(exprs) => exprs.iter().for_each(|e| visitor.visit_expr(e)),
+    }
+}
+
+/// A [`Fold`] rewrites an [`Expr`] tree, consuming it and building a new one. By default every
+/// node is rebuilt with its children folded: override [`Fold::fold_expr`] to replace the nodes
+/// of interest and call [`fold`] to go on with the children.
+///
+/// # Examples
+///
+/// ```
+/// #    use yarer::{ast::{fold, Expr, Fold}, parser::Parser};
+///
+///      /// Renames the variable 'x' to 'y'
+///      struct Rename;
+///
+///      impl Fold for Rename {
+///          fn fold_expr(&mut self, expr: Expr) -> Expr {
+///              match expr {
+///                  Expr::Variable { name, span } if name == "x" => {
+///                      Expr::Variable { name: "y".to_string(), span }
+///                  }
+///                  other => fold(self, other),
+///              }
+///          }
+///      }
+///
+///      let expr = Rename.fold_expr(Parser::parse_expr("sin(x)^2 + 1").unwrap());
+///      assert_eq!(expr.to_string(), "sin(y)^2 + 1");
+///  ```
+///
+pub trait Fold {
+    /// Rewrites a node of the tree and its children
+    ///
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold(self, expr)
+    }
+}
+
+/// Rebuilds a node with its children rewritten by a [`Fold`]
+///
+pub fn fold<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Number { .. } | Expr::Variable { .. } => expr,
+        Expr::Unary { op, operand, span } => Expr::Unary {
+            op,
+            operand: Box::new(folder.fold_expr(*operand)),
+            span,
+        },
+        Expr::Binary {
+            op,
+            left,
+            right,
+            span,
+        } => Expr::Binary {
+            op,
+            left: Box::new(folder.fold_expr(*left)),
+            right: Box::new(folder.fold_expr(*right)),
+            span,
+        },
+        Expr::Call {
+            function,
+            args,
+            span,
+        } => Expr::Call {
+            function,
+            args: args.into_iter().map(|a| folder.fold_expr(a)).collect(),
+            span,
+        },
+        Expr::Assign {
+            name,
+            target,
+            value,
+            span,
+        } => Expr::Assign {
+            name,
+            target,
+            value: Box::new(folder.fold_expr(*value)),
+            span,
+        },
+        Expr::Sequence(exprs) => {
+            Expr::Sequence(exprs.into_iter().map(|e| folder.fold_expr(e)).collect())
+        }
+    }
+}
+
+/// The [`Visitor`] behind [`Expr::to_rpn`]: it pushes every node after its children
+///
+struct Postfix(Vec<(Token<'static>, Span)>);
+
+impl Visitor for Postfix {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number { value, span } => self.0.push((Token::Operand(value.clone()), *span)),
+            Expr::Variable { name, span } => {
+                self.0.push((Token::Variable(Cow::Owned(name.clone())), *span));
+            }
+            Expr::Unary { op, span, .. } | Expr::Binary { op, span, .. } => {
+                walk(self, expr);
+                self.0.push((Token::Operator(*op), *span));
+            }
+            Expr::Call {
+                function,
+                args,
+                span,
+            } => {
+                walk(self, expr);
+                let call = match function {
+                    Callee::Math(fun) => Token::Function(*fun, args.len()),
+                    Callee::User(name) => Token::UserFunction(Cow::Owned(name.clone()), args.len()),
+                };
+                self.0.push((call, *span));
+            }
+            Expr::Assign {
+                name, target, span, ..
+            } => {
+                self.0.push((Token::Variable(Cow::Owned(name.clone())), *target));
+                walk(self, expr);
+                self.0.push((Token::Operator(Operator::Eql), *span));
+            }
+            Expr::Sequence(exprs) => {
+                for (i, e) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        self.0.push((Token::SemiColon, Span::new(e.span().start, e.span().start)));
+                    }
+                    self.visit_expr(e);
+                }
+            }
+        }
+    }
+}
+
+/// Walks the tree in order of evaluation, so a variable is free only if it's read before being assigned
+///
+impl Visitor for Dependencies {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable { name, .. } => {
+                let name = name.to_lowercase();
+                if !self.assigned.contains(&name) {
+                    if CONSTANTS.contains(&name.as_str()) {
+                        self.constants.insert(name);
+                    } else {
+                        self.variables.insert(name);
+                    }
+                }
+            }
+            Expr::Call { function, .. } => {
+                self.functions.insert(function.to_string().to_lowercase());
+                walk(self, expr);
+            }
+            Expr::Assign { name, .. } => {
+                walk(self, expr);
+                self.assigned.insert(name.to_lowercase());
+            }
+            _ => walk(self, expr),
+        }
+    }
+}
+
+/// The [`Visitor`] behind [`Expr::span`]: the smallest [Span] covering all the nodes
+///
+struct Extent(Option<Span>);
+
+impl Visitor for Extent {
+    fn visit_expr(&mut self, expr: &Expr) {
+        let spans = match expr {
+            Expr::Sequence(_) => vec![],
+            Expr::Assign { target, span, .. } => vec![*target, *span],
+            _ => vec![expr.span()],
+        };
+        for span in spans {
+            self.0 = Some(match self.0 {
+                Some(s) => Span::new(s.start.min(span.start), s.end.max(span.end)),
+                None => span,
+            });
+        }
+        walk(self, expr);
+    }
+}
+
+impl Display for Callee {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Callee::Math(fun) => write!(f, "{}", fun.to_string().to_lowercase()),
+            Callee::User(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Brackets around a child of an operator, if its priority is lower
+///
+struct Operand<'a>(&'a Expr, bool);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Let's display an [`Expr`] as an infix expression, with just the brackets that are needed
+///
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (priority, associate) = self.priority();
+        match self {
+            Expr::Number { value, .. } => write!(f, "{value}"),
+            Expr::Variable { name, .. } => write!(f, "{name}"),
+            Expr::Unary {
+                op: Operator::Fac,
+                operand,
+                ..
+            } => write!(f, "{}!", Operand(operand, operand.priority().0 < priority)),
+            Expr::Unary {
+                op: Operator::Not,
+                operand,
+                ..
+            } => write!(f, "not {}", Operand(operand, operand.priority().0 < priority)),
+            Expr::Unary { operand, .. } => {
+                write!(f, "-{}", Operand(operand, operand.priority().0 < priority))
+            }
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                let (left_priority, right_priority) = (left.priority().0, right.priority().0);
+                let left_brackets = left_priority < priority
+                    || left_priority == priority && associate == Associate::RightAssociative;
+                let right_brackets = right_priority < priority
+                    || right_priority == priority && associate == Associate::LeftAssociative;
+                let left = Operand(left, left_brackets);
+                let right = Operand(right, right_brackets);
+                match op {
+                    Operator::Pow => write!(f, "{left}^{right}"),
+                    _ => write!(f, "{left} {op} {right}"),
+                }
+            }
+            Expr::Call { function, args, .. } => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{function}({})", args.join(", "))
+            }
+            Expr::Assign { name, value, .. } => write!(f, "{name} = {value}"),
+            Expr::Sequence(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(ToString::to_string).collect();
+                write!(f, "{}", exprs.join("; "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_to_rpn() {
+        let expr = Parser::parse_expr("1+2").unwrap();
+        assert_eq!(
+            expr.to_rpn(),
+            vec![
+                (Token::Operand(Number::NaturalNumber(BigInt::from(1u8))), Span::new(0, 1)),
+                (Token::Operand(Number::NaturalNumber(BigInt::from(2u8))), Span::new(2, 3)),
+                (Token::Operator(Operator::Add), Span::new(1, 2)),
+            ]
+        );
+        let rpn: String = Parser::parse_expr("x = max(3 * 4, 5 * -6); f(x)!")
+            .unwrap()
+            .to_rpn()
+            .iter()
+            .map(|(t, _)| t.to_string())
+            .collect();
+        assert_eq!(rpn, "(x)(3)(4)(*)(5)(6)(#)(*)(Max/2)(=)(;)(x)(f/1)(!)");
+    }
+
+    #[test]
+    fn test_display() {
+        for (expr, expected) in [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("1-(2-3)", "1 - (2 - 3)"),
+            ("(1-2)-3", "1 - 2 - 3"),
+            ("2^3^4", "2^3^4"),
+            ("(2^3)^4", "(2^3)^4"),
+            ("-2^2", "-2^2"),
+            ("-(2^2)", "-(2^2)"),
+            ("(x+1)!", "(x + 1)!"),
+            ("x=y=max(1,2)", "x = y = max(1, 2)"),
+            ("a=1;b=2;a/b", "a = 1; b = 2; a / b"),
+        ] {
+            assert_eq!(Parser::parse_expr(expr).unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let names = |set: BTreeSet<String>| set.into_iter().collect::<Vec<_>>();
+
+        let deps = Parser::parse_expr("x = X + 1; y = x * E; Hyp(x, y) + sqrt(z)").unwrap().dependencies();
+        assert_eq!(names(deps.variables), ["x", "z"]);
+        assert_eq!(names(deps.constants), ["e"]);
+        assert_eq!(names(deps.assigned), ["x", "y"]);
+        assert_eq!(names(deps.functions), ["hyp", "sqrt"]);
+
+        // a constant assigned before being read is a variable like the others
+        let deps = Parser::parse_expr("pi = 3; pi * r").unwrap().dependencies();
+        assert_eq!(names(deps.variables), ["r"]);
+        assert!(deps.constants.is_empty());
+
+        let deps = Parser::parse_expr("I * x; i = 2; i").unwrap().dependencies_with(true);
+        assert_eq!(names(deps.variables), ["x"]);
+        assert_eq!(names(deps.constants), ["i"]);
+        let deps = Parser::parse_expr("i = 2; i").unwrap().dependencies_with(true);
+        assert!(deps.constants.is_empty());
+    }
+
+    #[test]
+    fn test_span() {
+        let expr = Parser::parse_expr("x = 1; y + 10").unwrap();
+        assert_eq!(expr.span(), Span::new(0, 13));
+    }
+}
@@ -0,0 +1,512 @@
+use crate::token::MathFunction;
+
+use bigdecimal::{BigDecimal, Context, RoundingMode};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::num::NonZeroU64;
+
+/// Extra digits carried during the intermediate steps of a computation,
+/// so that the rounding errors don't pollute the requested digits
+///
+const GUARD_DIGITS: u64 = 10;
+
+/// Beyond this bound `exp(x)` can't be represented by a [`BigDecimal`] anymore
+///
+const EXP_LIMIT: i64 = 1_000_000;
+
+/// Beyond this order of magnitude an angle can't be reduced in a reasonable time
+///
+const ANGLE_MAGNITUDE_LIMIT: i64 = 1_000;
+
+/// The [`BigMath`] struct evaluates the transcendental functions with an arbitrary
+/// number of significant digits, using series expansions and Newton's method on [`BigDecimal`]s.
+///
+/// Every function returns [`None`] when the argument is outside of its domain (i.e. 'ln(0)')
+///
+pub struct BigMath {
+    digits: u64,
+    ctx: Context,
+}
+
+impl BigMath {
+    /// Builds a new [`BigMath`] that computes results with `digits` significant digits
+    ///
+    #[must_use]
+    pub fn new(digits: u64) -> BigMath {
+        let digits = digits.max(1);
+        BigMath {
+            digits,
+            ctx: Self::context(digits.saturating_add(GUARD_DIGITS)),
+        }
+    }
+
+    fn context(precision: u64) -> Context {
+        Context::new(
+            NonZeroU64::new(precision).unwrap_or(NonZeroU64::MIN),
+            RoundingMode::HalfEven,
+        )
+    }
+
+    /// A [`BigMath`] carrying `extra` more digits, for the computations that lose precision
+    ///
+    fn extended(&self, extra: u64) -> BigMath {
+        BigMath {
+            digits: self.digits.saturating_add(extra),
+            ctx: Self::context(self.digits.saturating_add(GUARD_DIGITS + extra)),
+        }
+    }
+
+    /// Applies a single argument [`MathFunction`] to a rational value
+    ///
+    #[must_use]
+    pub fn apply(&self, fun: MathFunction, value: &BigRational) -> Option<BigRational> {
+        let x = self.to_decimal(value);
+        let res = match fun {
+            MathFunction::Sin => self.sin(&x)?,
+            MathFunction::Cos => self.cos(&x)?,
+            MathFunction::Tan => self.tan(&x)?,
+            MathFunction::ASin => self.asin(&x)?,
+            MathFunction::ACos => self.acos(&x)?,
+            MathFunction::ATan => self.atan(&x),
+            MathFunction::Ln => self.ln(&x)?,
+            MathFunction::Log => self.log10(&x)?,
+            MathFunction::Sqrt => self.sqrt(&x)?,
+            MathFunction::Exp => self.exp(&x)?,
+            MathFunction::Pdf => self.pdf(&x)?,
+            MathFunction::Cdf => self.cdf(&x)?,
+            MathFunction::Abs => return Some(value.abs()),
+            MathFunction::Floor => return Some(value.floor()),
+            MathFunction::Ceil => return Some(value.ceil()),
+            MathFunction::Round => return Some(value.round()),
+            MathFunction::Max
+            | MathFunction::Min
+            | MathFunction::Sum
+            | MathFunction::Avg
+            | MathFunction::Hypot
+            | MathFunction::None => return None,
+        };
+        Some(self.to_rational(&res))
+    }
+
+    /// Raises `base` to a rational `exponent`, as exp(exponent * ln(base))
+    ///
+    #[must_use]
+    pub fn pow(&self, base: &BigRational, exponent: &BigRational) -> Option<BigRational> {
+        if base.is_zero() {
+            return exponent.is_positive().then(BigRational::zero);
+        }
+        let x = self.to_decimal(base);
+        let y = self.to_decimal(exponent);
+        let res = self.exp(&self.mul(&y, &self.ln(&x)?))?;
+        Some(self.to_rational(&res))
+    }
+
+    /// Pi computed with Machin's formula: pi = 16 atan(1/5) - 4 atan(1/239)
+    ///
+    #[must_use]
+    pub fn pi(&self) -> BigDecimal {
+        let a = self.atan_series(&self.div(&BigDecimal::one(), &BigDecimal::from(5)));
+        let b = self.atan_series(&self.div(&BigDecimal::one(), &BigDecimal::from(239)));
+        self.round(a * BigDecimal::from(16) - b * BigDecimal::from(4))
+    }
+
+    /// Euler's number, e = exp(1)
+    ///
+    #[must_use]
+    pub fn e(&self) -> BigDecimal {
+        self.exp_series(&BigDecimal::one())
+    }
+
+    /// The golden ratio, phi = (1 + sqrt(5)) / 2
+    ///
+    #[must_use]
+    pub fn phi(&self) -> BigDecimal {
+        let sqrt5 = BigDecimal::from(5)
+            .sqrt_with_context(&self.ctx)
+            .unwrap_or_default();
+        self.round((BigDecimal::one() + sqrt5).half())
+    }
+
+    /// The Euler–Mascheroni constant, computed with the Brent–McMillan algorithm
+    ///
+    #[must_use]
+    pub fn gamma(&self) -> BigDecimal {
+        // the error is about pi * exp(-4n), so 4n must exceed digits * ln(10)
+        let order = self.digits * 576 / 1000 + 2;
+        let big_n = BigDecimal::from(order);
+        let ext = self.extended(self.digits / 10 + 2);
+        let n2 = ext.mul(&big_n, &big_n);
+
+        let mut term_a = -ext.ln(&big_n).unwrap_or_default();
+        let mut term_b = BigDecimal::one();
+        let mut sum_a = term_a.clone();
+        let mut sum_b = term_b.clone();
+        let eps = ext.epsilon();
+        for k in 1u64.. {
+            let k = BigDecimal::from(k);
+            let k2 = ext.mul(&k, &k);
+            term_b = ext.div(&ext.mul(&term_b, &n2), &k2);
+            term_a = ext.div(&(ext.div(&ext.mul(&term_a, &n2), &k) + &term_b), &k);
+            sum_a = ext.round(sum_a + &term_a);
+            sum_b = ext.round(sum_b + &term_b);
+            let threshold = &eps * &sum_b;
+            if term_a.abs() < threshold && term_b < threshold {
+                break;
+            }
+        }
+        self.div(&sum_a, &sum_b)
+    }
+
+    /// Rounds a [`BigDecimal`] to the requested significant digits and converts it, exactly, into a [`BigRational`]
+    ///
+    #[must_use]
+    pub fn to_rational(&self, x: &BigDecimal) -> BigRational {
+        let rounded = Self::context(self.digits).round_decimal_ref(x);
+        let (int, scale) = rounded.into_bigint_and_scale();
+        let ten = BigInt::from(10);
+        match u32::try_from(scale.unsigned_abs()) {
+            Ok(s) if scale >= 0 => BigRational::new(int, num_traits::pow(ten, s as usize)),
+            Ok(s) => BigRational::from_integer(int * num_traits::pow(ten, s as usize)),
+            Err(_) => BigRational::zero(),
+        }
+    }
+
+    /// Converts a [`BigRational`] into a [`BigDecimal`] rounded to the requested significant digits
+    ///
+    #[must_use]
+    pub fn rounded(&self, r: &BigRational) -> BigDecimal {
+        Self::context(self.digits)
+            .round_decimal(self.to_decimal(r))
+            .normalized()
+    }
+
+    /// Converts a [`BigRational`] into a [`BigDecimal`] with the working precision
+    ///
+    #[must_use]
+    pub fn to_decimal(&self, r: &BigRational) -> BigDecimal {
+        let numer = BigDecimal::from(r.numer().clone());
+        if r.denom().is_one() {
+            return numer;
+        }
+        self.div(&numer, &BigDecimal::from(r.denom().clone()))
+    }
+
+    fn round(&self, x: BigDecimal) -> BigDecimal {
+        self.ctx.round_decimal(x)
+    }
+
+    fn mul(&self, a: &BigDecimal, b: &BigDecimal) -> BigDecimal {
+        self.ctx.multiply(a, b)
+    }
+
+    fn div(&self, a: &BigDecimal, b: &BigDecimal) -> BigDecimal {
+        self.ctx.multiply(a, &self.ctx.invert(b))
+    }
+
+    /// The smallest value that still matters with the working precision
+    ///
+    fn epsilon(&self) -> BigDecimal {
+        let scale = i64::try_from(self.digits + GUARD_DIGITS).unwrap_or(i64::MAX);
+        BigDecimal::new(BigInt::one(), scale)
+    }
+
+    fn sqrt(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        x.sqrt_with_context(&self.ctx)
+    }
+
+    fn exp(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        if x.abs() > EXP_LIMIT {
+            return None;
+        }
+        if x.is_negative() {
+            return Some(self.ctx.invert(&self.exp(&-x)?));
+        }
+        Some(self.exp_series(x))
+    }
+
+    /// exp(x) for a non-negative x. The argument is halved k times until it's below 1/2,
+    /// then the Taylor series result is squared k times
+    ///
+    fn exp_series(&self, x: &BigDecimal) -> BigDecimal {
+        let half = BigDecimal::new(BigInt::from(5), 1);
+        let mut r = x.clone();
+        let mut k: u64 = 0;
+        while r > half {
+            r = r.half();
+            k += 1;
+        }
+        // every squaring doubles the relative error
+        let ext = self.extended(k / 3 + 1);
+        let r = ext.round(r);
+
+        let eps = ext.epsilon();
+        let mut sum = BigDecimal::one();
+        let mut term = BigDecimal::one();
+        for n in 1u64.. {
+            term = ext.div(&ext.mul(&term, &r), &BigDecimal::from(n));
+            if term.abs() < eps {
+                break;
+            }
+            sum = ext.round(sum + &term);
+        }
+        for _ in 0..k {
+            sum = ext.mul(&sum, &sum);
+        }
+        self.round(sum)
+    }
+
+    /// atanh(z) = z + z^3/3 + z^5/5 + ... converges fast for small values of z
+    ///
+    fn atanh_series(&self, z: &BigDecimal) -> BigDecimal {
+        let eps = self.epsilon();
+        let z2 = self.mul(z, z);
+        let mut power = z.clone();
+        let mut sum = z.clone();
+        for n in 1u64.. {
+            power = self.mul(&power, &z2);
+            let term = self.div(&power, &BigDecimal::from(2 * n + 1));
+            if term.abs() < eps {
+                break;
+            }
+            sum = self.round(sum + term);
+        }
+        sum
+    }
+
+    /// ln(m) = 2 atanh((m - 1) / (m + 1))
+    ///
+    fn ln_atanh(&self, m: &BigDecimal) -> BigDecimal {
+        let z = self.div(&(m - BigDecimal::one()), &(m + BigDecimal::one()));
+        self.atanh_series(&z).double()
+    }
+
+    fn ln(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        if !x.is_positive() {
+            return None;
+        }
+        // x = m * 10^j * 2^k with m in [1, 2)
+        let j = x.order_of_magnitude();
+        let (int, scale) = x.as_bigint_and_scale();
+        let mut m = BigDecimal::new(int.into_owned(), scale + j);
+        let mut k: i64 = 0;
+        let two = BigDecimal::from(2);
+        while m >= two {
+            m = m.half();
+            k += 1;
+        }
+        let ext = self.extended(u64::try_from(j.unsigned_abs().to_string().len()).unwrap_or(0));
+        let ln2 = ext.ln_atanh(&two);
+        let ln10 = ext.ln_atanh(&BigDecimal::new(BigInt::from(125), 2)) + &ln2 * BigDecimal::from(3);
+
+        let res = ext.ln_atanh(&m) + ln2 * BigDecimal::from(k) + ln10 * BigDecimal::from(j);
+        Some(self.round(res))
+    }
+
+    fn log10(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let ln10 = self.ln(&BigDecimal::from(10))?;
+        Some(self.div(&self.ln(x)?, &ln10))
+    }
+
+    /// Reduces x in the interval [-pi, pi] subtracting the closest multiple of 2pi
+    ///
+    fn reduce_angle(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let magnitude = x.order_of_magnitude();
+        if magnitude > ANGLE_MAGNITUDE_LIMIT {
+            return None;
+        }
+        let ext = self.extended(u64::try_from(magnitude.max(0)).unwrap_or(0) + 1);
+        let two_pi = ext.pi().double();
+        let turns = ext.div(x, &two_pi).round(0);
+        Some(self.round(x - ext.mul(&turns, &two_pi)))
+    }
+
+    /// The Taylor series shared by sin (starting from x) and cos (starting from 1)
+    ///
+    fn sin_cos_series(&self, r: &BigDecimal, first: BigDecimal, start: u64) -> BigDecimal {
+        let eps = self.epsilon();
+        let r2 = self.mul(r, r);
+        let mut term = first.clone();
+        let mut sum = first;
+        let mut n = start;
+        loop {
+            term = -self.div(&self.mul(&term, &r2), &BigDecimal::from((n + 1) * (n + 2)));
+            if term.abs() < eps {
+                break;
+            }
+            sum = self.round(sum + &term);
+            n += 2;
+        }
+        sum
+    }
+
+    fn sin(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let r = self.reduce_angle(x)?;
+        Some(self.sin_cos_series(&r, r.clone(), 1))
+    }
+
+    fn cos(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let r = self.reduce_angle(x)?;
+        Some(self.sin_cos_series(&r, BigDecimal::one(), 0))
+    }
+
+    fn tan(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let c = self.cos(x)?;
+        if c.is_zero() {
+            return None;
+        }
+        Some(self.div(&self.sin(x)?, &c))
+    }
+
+    /// atan(z) = z - z^3/3 + z^5/5 - ... for |z| < 1
+    ///
+    fn atan_series(&self, z: &BigDecimal) -> BigDecimal {
+        let eps = self.epsilon();
+        let z2 = self.mul(z, z);
+        let mut power = z.clone();
+        let mut sum = z.clone();
+        for n in 1u64.. {
+            power = -self.mul(&power, &z2);
+            let term = self.div(&power, &BigDecimal::from(2 * n + 1));
+            if term.abs() < eps {
+                break;
+            }
+            sum = self.round(sum + term);
+        }
+        sum
+    }
+
+    fn atan(&self, x: &BigDecimal) -> BigDecimal {
+        let one = BigDecimal::one();
+        if x.abs() > one {
+            // atan(x) = sign(x) * pi/2 - atan(1/x)
+            let half_pi = self.pi().half();
+            let inv = self.atan(&self.ctx.invert(x));
+            let res = if x.is_negative() { -half_pi - inv } else { half_pi - inv };
+            return self.round(res);
+        }
+        // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), applied 3 times to speed up the series
+        let mut z = x.clone();
+        for _ in 0..3 {
+            let root = self.sqrt(&(&one + self.mul(&z, &z))).unwrap_or_default();
+            z = self.div(&z, &(&one + root));
+        }
+        self.round(self.atan_series(&z) * BigDecimal::from(8))
+    }
+
+    fn asin(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let one = BigDecimal::one();
+        match x.abs().cmp(&one) {
+            std::cmp::Ordering::Greater => None,
+            std::cmp::Ordering::Equal => {
+                let half_pi = self.pi().half();
+                Some(if x.is_negative() { -half_pi } else { half_pi })
+            }
+            std::cmp::Ordering::Less => {
+                let root = self.sqrt(&(&one - self.mul(x, x)))?;
+                Some(self.atan(&self.div(x, &root)))
+            }
+        }
+    }
+
+    fn acos(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let asin = self.asin(x)?;
+        Some(self.round(self.pi().half() - asin))
+    }
+
+    /// The standard normal probability density function, exp(-x^2/2) / sqrt(2pi)
+    ///
+    fn pdf(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let num = self.exp(&-self.mul(x, x).half())?;
+        let den = self.sqrt(&self.pi().double())?;
+        Some(self.div(&num, &den))
+    }
+
+    /// The standard normal cumulative distribution function, (1 + erf(x / sqrt(2))) / 2
+    /// where erf(z) = 2/sqrt(pi) * exp(-z^2) * sum(2^n z^(2n+1) / (1*3*...*(2n+1))) has only positive terms
+    ///
+    fn cdf(&self, x: &BigDecimal) -> Option<BigDecimal> {
+        let z2 = self.mul(x, x).half();
+        // once z^2 exceeds the precision in nepers the result is 0 or 1
+        let limit = BigDecimal::from(self.digits + GUARD_DIGITS) * BigDecimal::new(BigInt::from(231), 2);
+        if z2 > limit {
+            return Some(if x.is_negative() { BigDecimal::zero() } else { BigDecimal::one() });
+        }
+        let ext = self.extended(z2.to_u64().unwrap_or(0) / 2 + 1);
+        let z = ext.div(x, &ext.sqrt(&BigDecimal::from(2))?);
+        let two_z2 = z2.double();
+
+        let eps = ext.epsilon();
+        let mut term = z.clone();
+        let mut sum = z;
+        for n in 1u64.. {
+            term = ext.div(&ext.mul(&term, &two_z2), &BigDecimal::from(2 * n + 1));
+            if term.abs() < eps {
+                break;
+            }
+            sum = ext.round(sum + &term);
+        }
+        let scale = ext.div(&BigDecimal::from(2), &ext.sqrt(&ext.pi())?);
+        let erf = ext.mul(&ext.mul(&scale, &ext.exp(&-z2)?), &sum);
+        Some(self.round((BigDecimal::one() + erf).half()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn digits_of(x: &BigDecimal, n: u64) -> String {
+        BigMath::context(n).round_decimal_ref(x).to_string()
+    }
+
+    #[test]
+    fn test_constants() {
+        let m = BigMath::new(50);
+        assert_eq!(
+            digits_of(&m.pi(), 40),
+            "3.141592653589793238462643383279502884197"
+        );
+        assert_eq!(
+            digits_of(&m.e(), 40),
+            "2.718281828459045235360287471352662497757"
+        );
+        assert_eq!(
+            digits_of(&m.phi(), 40),
+            "1.618033988749894848204586834365638117720"
+        );
+        assert_eq!(
+            digits_of(&m.gamma(), 40),
+            "0.5772156649015328606065120900824024310422"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let m = BigMath::new(40);
+        let two = BigDecimal::from(2);
+        assert_eq!(
+            digits_of(&m.sqrt(&two).unwrap(), 30),
+            "1.41421356237309504880168872421"
+        );
+        assert_eq!(
+            digits_of(&m.ln(&two).unwrap(), 30),
+            "0.693147180559945309417232121458"
+        );
+        assert_eq!(
+            digits_of(&m.sin(&BigDecimal::one()).unwrap(), 30),
+            "0.841470984807896506652502321630"
+        );
+        assert_eq!(
+            digits_of(&m.atan(&BigDecimal::one()), 30),
+            "0.785398163397448309615660845820"
+        );
+        assert_eq!(
+            digits_of(&m.exp(&BigDecimal::from_str("-2.5").unwrap()).unwrap(), 30),
+            "0.0820849986238987951695286744672"
+        );
+        assert!(m.ln(&BigDecimal::zero()).is_none());
+        assert!(m.asin(&two).is_none());
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::PathBuf;
+
+use yarer::format::Notation;
+use yarer::function::Function;
+use yarer::parser::Parser as ExprParser;
+use yarer::session::{Session, CONSTANTS, IMAGINARY_UNIT};
+use yarer::token::{MathFunction, Number, Operator, Token};
+
+/// The REPL commands, with their description
+///
+static COMMANDS: [(&str, &str); 13] = [
+    (":vars", "lists the variables, with their values and types"),
+    (":funcs", "lists the built-in and the user functions, with their arities"),
+    (":unset NAME...", "removes the variables"),
+    (":reset", "removes all the variables, functions and cells"),
+    (":clear", "forgets the previous results, so the next one is $1 again"),
+    (":rpn EXPR", "shows the postfix form of an expression"),
+    (":help [FUNCTION]", "describes a function, or lists the commands"),
+    (":mode", "shows the numeric modes of the session"),
+    (":mode MODE", "switches to 'float', 'precision N', 'strict', 'lenient', 'complex', 'real', 'implicit' or 'explicit' mode"),
+    (":mode FORMAT", "writes the results as 'auto', 'fraction', 'fixed N', 'sig N', 'sci N' or 'eng N'"),
+    (":mode grouped", "separates the thousands of the results, ':mode ungrouped' doesn't"),
+    (":save [FILE]", "saves the session to FILE, or to the --session file"),
+    (":load [FILE]", "loads the session from FILE, or from the --session file"),
+];
+
+/// The names of the REPL commands, with their ':'
+///
+pub fn names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = COMMANDS
+        .iter()
+        .filter_map(|(command, _)| command.split_whitespace().next())
+        .collect();
+    names.dedup();
+    names
+}
+
+/// Runs a REPL command, the text after ':'. The commands are kept apart from the expressions,
+/// so their names are still valid variables. Returns false if there's an error
+///
+pub fn run(session: &Session, command: &str, session_file: Option<&PathBuf>) -> bool {
+    let (name, arg) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+    let arg = arg.trim();
+    let result = match name {
+        "vars" => {
+            vars(session);
+            Ok(())
+        }
+        "funcs" => {
+            funcs(session);
+            Ok(())
+        }
+        "unset" => unset(session, arg),
+        "reset" => {
+            session.reset();
+            Ok(())
+        }
+        "clear" => {
+            session.clear_results();
+            Ok(())
+        }
+        "rpn" => rpn(session, arg),
+        "help" => help(session, arg),
+        "mode" => mode(session, arg),
+        "save" | "load" => {
+            let path = match arg {
+                "" => session_file.cloned(),
+                file => Some(PathBuf::from(file)),
+            };
+            match path {
+                Some(path) if name == "save" => session.save(path).map_err(|e| e.to_string()),
+                Some(path) => session.load(path).map_err(|e| e.to_string()),
+                None => Err(format!("no session file, use :{name} FILE")),
+            }
+        }
+        _ => Err(format!("unknown command ':{name}', type :help for the list of commands")),
+    };
+    if let Err(e) = &result {
+        eprintln!("Error: {e}");
+    }
+    result.is_ok()
+}
+
+/// Prints all the variables, sorted by name, with their values and types
+///
+fn vars(session: &Session) {
+    for (name, value) in session.variables() {
+        let kind = match value {
+            Number::NaturalNumber(_) => "natural",
+            Number::DecimalNumber(_) => "decimal",
+            Number::ComplexNumber(_) => "complex",
+        };
+        let origin = match session.cell(&name) {
+            Some(cell) => format!(", := {}", cell.formula.source().trim()),
+            None if CONSTANTS.contains(&name.as_str()) => ", constant".to_string(),
+            None if name == IMAGINARY_UNIT && session.is_complex() => ", constant".to_string(),
+            None => String::new(),
+        };
+        println!("{name} = {} ({kind}{origin})", session.format_number(&value));
+    }
+}
+
+/// Prints the built-in functions and then the ones of the session, with their arities
+///
+fn funcs(session: &Session) {
+    for fun in MathFunction::ALL {
+        println!("{:<8} {:<12} {}", fun.name(), fun.arity().to_string(), fun.description());
+    }
+    for (name, fun) in session.functions() {
+        let arity = fun.arity().to_string();
+        match fun {
+            Function::User(user) => println!("{name:<8} {arity:<12} {name}({}) = {}", user.params.join(", "), user.body),
+            Function::Native { .. } => println!("{name:<8} {arity:<12} native function"),
+        }
+    }
+}
+
+fn unset(session: &Session, names: &str) -> Result<(), String> {
+    if names.is_empty() {
+        return Err("no variable to unset, use :unset NAME".to_string());
+    }
+    let unknown: Vec<&str> = names
+        .split_whitespace()
+        .filter(|name| !session.unset(name))
+        .collect();
+    match unknown.as_slice() {
+        [] => Ok(()),
+        _ => Err(format!("unknown variable(s) {}", unknown.join(", "))),
+    }
+}
+
+/// Prints the postfix form of an expression, i.e. '3 4 * 5 6 * +'. The functions called
+/// with more than one argument are followed by their count, as in 'max/2'
+///
+fn rpn(session: &Session, expr: &str) -> Result<(), String> {
+    let is_function = |name: &str| session.function(name).is_some();
+    let tree = ExprParser::parse_expr_calling(expr, session.is_implicit(), is_function)
+        .map_err(|errors| errors[0].to_string())?;
+    let tokens: Vec<String> = tree
+        .to_rpn()
+        .iter()
+        .map(|(token, _)| match token {
+            Token::Operator(Operator::Une) => "neg".to_string(),
+            Token::Function(fun, 1) => fun.name(),
+            Token::Function(fun, n) => format!("{}/{n}", fun.name()),
+            Token::UserFunction(name, 1) => name.to_string(),
+            Token::UserFunction(name, n) => format!("{name}/{n}"),
+            other => other.symbol(),
+        })
+        .collect();
+    println!("{}", tokens.join(" "));
+    Ok(())
+}
+
+fn help(session: &Session, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        for (command, description) in COMMANDS {
+            println!("{command:<18} {description}");
+        }
+        return Ok(());
+    }
+    if let Some(fun) = MathFunction::ALL.into_iter().find(|f| f.name() == name.to_lowercase()) {
+        println!("{}: {} ({} argument(s))", fun.name(), fun.description(), fun.arity());
+        return Ok(());
+    }
+    match session.function(name) {
+        Some(Function::User(user)) => {
+            println!("{name}({}) = {}", user.params.join(", "), user.body);
+            Ok(())
+        }
+        Some(fun @ Function::Native { .. }) => {
+            println!("{name}: native function ({} argument(s))", fun.arity());
+            Ok(())
+        }
+        None => Err(format!("unknown function '{name}'")),
+    }
+}
+
+/// Shows the numeric and output modes, or switches one of them
+///
+fn mode(session: &Session, mode: &str) -> Result<(), String> {
+    let words: Vec<&str> = mode.split_whitespace().collect();
+    let mut format = session.number_format();
+    match words.as_slice() {
+        [] => {
+            match session.precision() {
+                Some(digits) => println!("precision {digits}"),
+                None => println!("float"),
+            }
+            println!("{}", if session.is_strict() { "strict" } else { "lenient" });
+            println!("{}", if session.is_complex() { "complex" } else { "real" });
+            println!("{}", if session.is_implicit() { "implicit" } else { "explicit" });
+            println!("{}", format.notation.to_string().replace(':', " "));
+            println!("{}", if format.separator.is_some() { "grouped" } else { "ungrouped" });
+        }
+        ["float"] => session.set_precision(None).map_err(|e| e.to_string())?,
+        ["precision", digits] => {
+            let digits = digits.parse().map_err(|_| format!("invalid precision '{digits}'"))?;
+            session.set_precision(Some(digits)).map_err(|e| e.to_string())?;
+        }
+        ["strict"] => session.set_strict(true),
+        ["lenient"] => session.set_strict(false),
+        ["complex"] => session.set_complex(true),
+        ["real"] => session.set_complex(false),
+        ["implicit"] => session.set_implicit(true),
+        ["explicit"] => session.set_implicit(false),
+        ["grouped"] => {
+            format.separator = Some(',');
+            session.set_number_format(format);
+        }
+        ["ungrouped"] => {
+            format.separator = None;
+            session.set_number_format(format);
+        }
+        // a format, as 'fixed 2'
+        _ => {
+            format.notation = words.join(":").parse::<Notation>()?;
+            session.set_number_format(format);
+        }
+    }
+    Ok(())
+}
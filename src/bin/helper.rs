@@ -0,0 +1,299 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+use yarer::error::Span;
+use yarer::function::{Arity, Function};
+use yarer::parser::Parser as ExprParser;
+use yarer::session::Session;
+use yarer::token::{Bracket, MathFunction, Operator, Token};
+
+use crate::commands;
+
+static NUMBER: &str = "\x1b[33m";
+static OPERATOR: &str = "\x1b[36m";
+static FUNCTION: &str = "\x1b[34m";
+static UNKNOWN: &str = "\x1b[31m";
+static MATCHING_BRACKET: &str = "\x1b[1;4m";
+static COMMAND: &str = "\x1b[1m";
+static HINT: &str = "\x1b[90m";
+static RESET: &str = "\x1b[0m";
+
+/// The rustyline [Helper] of the REPL. It completes, highlights and hints the expressions
+/// with the names of the session, splitting them with the same tokenizer used to parse them
+///
+pub struct YarerHelper<'a> {
+    session: &'a Session,
+}
+
+impl<'a> YarerHelper<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        YarerHelper { session }
+    }
+
+    /// Colours the tokens of `expr`, which starts at `offset` in the line. The names in `known`
+    /// are defined by the line itself (i.e. the parameters of a function definition), and the
+    /// bracket under the `cursor` is underlined together with its match
+    ///
+    fn colour(&self, expr: &str, offset: usize, cursor: usize, known: &[&str]) -> String {
+        let tokens = ExprParser::lex(expr);
+        let variables = self.session.variables();
+        let brackets = cursor
+            .checked_sub(offset)
+            .and_then(|cursor| matching_brackets(&tokens, cursor))
+            .unwrap_or_default();
+
+        let mut coloured = String::new();
+        let mut last = 0;
+        for (i, (token, span)) in tokens.iter().enumerate() {
+            // the digits of a superscript exponent are inside the span of its power
+            if span.start < last {
+                continue;
+            }
+            let assigned = matches!(tokens.get(i + 1), Some((Some(Token::Operator(Operator::Eql)), _)));
+            let colour = match token {
+                Some(Token::Operand(_)) => Some(NUMBER),
+                Some(Token::Operator(_) | Token::Comma | Token::SemiColon) => Some(OPERATOR),
+                Some(Token::Function(..)) => Some(FUNCTION),
+                Some(Token::UserFunction(name, _)) => {
+                    if self.session.function(name).is_some() || known.contains(&name.as_ref()) {
+                        Some(FUNCTION)
+                    } else {
+                        Some(UNKNOWN)
+                    }
+                }
+                Some(Token::Variable(name)) => {
+                    let result = name.strip_prefix('$').and_then(|n| self.session.result(n.parse().ok()?));
+                    let defined = variables.contains_key(&name.to_lowercase())
+                        || result.is_some()
+                        || known.iter().any(|k| k.eq_ignore_ascii_case(name));
+                    (!defined && !assigned).then_some(UNKNOWN)
+                }
+                Some(Token::Bracket(_)) => brackets.contains(&i).then_some(MATCHING_BRACKET),
+                None => Some(UNKNOWN),
+            };
+            coloured.push_str(&expr[last..span.start]);
+            match colour {
+                Some(colour) => coloured.push_str(&format!("{colour}{}{RESET}", &expr[span.start..span.end])),
+                None => coloured.push_str(&expr[span.start..span.end]),
+            }
+            last = span.end;
+        }
+        coloured.push_str(&expr[last..]);
+        coloured
+    }
+
+    /// The signature of the innermost function called at the end of `line`, if any
+    ///
+    fn signature(&self, line: &str) -> Option<String> {
+        let tokens = ExprParser::lex(line);
+        let mut calls: Vec<Option<&Token>> = Vec::new();
+        for (i, (token, _)) in tokens.iter().enumerate() {
+            match token {
+                Some(Token::Bracket(Bracket::Open)) => {
+                    let callee = i.checked_sub(1).and_then(|j| tokens[j].0.as_ref());
+                    calls.push(callee.filter(|t| matches!(t, Token::Function(..) | Token::UserFunction(..))));
+                }
+                Some(Token::Bracket(Bracket::Close)) => {
+                    calls.pop();
+                }
+                _ => (),
+            }
+        }
+        match calls.into_iter().rev().flatten().next()? {
+            Token::Function(fun, _) => Some(format!("{}({}): {}", fun.name(), parameters(fun.arity()), fun.description())),
+            Token::UserFunction(name, _) => match self.session.function(name)? {
+                Function::User(user) => Some(format!("{name}({}) = {}", user.params.join(", "), user.body)),
+                fun @ Function::Native { .. } => Some(format!("{name}({})", parameters(fun.arity()))),
+            },
+            _ => None,
+        }
+    }
+
+    /// The names that start with `prefix`: the functions are completed with their open bracket
+    ///
+    fn candidates(&self, prefix: &str) -> Vec<Pair> {
+        let prefix = prefix.to_lowercase();
+        let mut names: BTreeMap<String, String> = BTreeMap::new();
+        for fun in MathFunction::ALL {
+            names.insert(fun.name(), format!("{}(", fun.name()));
+        }
+        for name in self.session.functions().into_keys() {
+            names.insert(name.clone(), format!("{name}("));
+        }
+        for name in self.session.variables().into_keys() {
+            names.insert(name.clone(), name);
+        }
+        names
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .map(|(display, replacement)| Pair { display, replacement })
+            .collect()
+    }
+}
+
+/// The indexes of the bracket under the cursor, or just before it, and of its match
+///
+fn matching_brackets(tokens: &[(Option<Token>, Span)], cursor: usize) -> Option<[usize; 2]> {
+    let mut open: Vec<usize> = Vec::new();
+    let mut pairs: Vec<[usize; 2]> = Vec::new();
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Some(Token::Bracket(Bracket::Open)) => open.push(i),
+            Some(Token::Bracket(Bracket::Close)) => pairs.extend(open.pop().map(|o| [o, i])),
+            _ => (),
+        }
+    }
+    let at = |i: &usize| tokens[*i].1.start == cursor;
+    let before = |i: &usize| tokens[*i].1.end == cursor;
+    pairs
+        .iter()
+        .find(|pair| pair.iter().any(at))
+        .or_else(|| pairs.iter().find(|pair| pair.iter().any(before)))
+        .copied()
+}
+
+/// The parameters of a function with an [Arity], i.e. 'x' or 'x1, x2, ...'
+///
+fn parameters(arity: Arity) -> String {
+    let (n, variadic) = match arity {
+        Arity::Exact(n) => (n, false),
+        Arity::AtLeast(n) => (n, true),
+    };
+    let mut params: Vec<String> = match n {
+        1 if !variadic => vec!["x".to_string()],
+        n => (1..=n).map(|i| format!("x{i}")).collect(),
+    };
+    if variadic {
+        params.push("...".to_string());
+    }
+    params.join(", ")
+}
+
+/// A hint that is only shown after the cursor, and never inserted in the line
+///
+pub struct Signature(String);
+
+impl Hint for Signature {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Completer for YarerHelper<'_> {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rfind(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &line[start..pos];
+        if line[..start].trim_start() == ":" {
+            let commands = commands::names()
+                .into_iter()
+                .filter_map(|c| c.strip_prefix(':'))
+                .filter(|c| c.starts_with(word))
+                .map(|c| Pair { display: c.to_string(), replacement: c.to_string() })
+                .collect();
+            return Ok((start, commands));
+        }
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok((pos, Vec::new()));
+        }
+        Ok((start, self.candidates(word)))
+    }
+}
+
+impl Hinter for YarerHelper<'_> {
+    type Hint = Signature;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<Signature> {
+        if pos < line.len() {
+            return None;
+        }
+        self.signature(line).map(|s| Signature(format!("  {s}")))
+    }
+}
+
+impl Highlighter for YarerHelper<'_> {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if line.trim().eq_ignore_ascii_case("quit") {
+            return Cow::Owned(format!("{COMMAND}{line}{RESET}"));
+        }
+        if let Some(command) = line.trim_start().strip_prefix(':') {
+            let name = command.split_whitespace().next().unwrap_or_default();
+            let end = line.len() - command.len() + command.find(name).unwrap_or(0) + name.len();
+            let rest = match name {
+                "rpn" => self.colour(&line[end..], end, pos, &[]),
+                _ => line[end..].to_string(),
+            };
+            return Cow::Owned(format!("{COMMAND}{}{RESET}{rest}", &line[..end]));
+        }
+        if let Some((_, formula)) = ExprParser::parse_binding(line) {
+            let start = line.len() - formula.len();
+            return Cow::Owned(format!("{}{}", &line[..start], self.colour(formula, start, pos, &[])));
+        }
+        if let Some((name, mut params, _)) = ExprParser::parse_definition(line) {
+            params.push(name);
+            return Cow::Owned(self.colour(line, 0, pos, &params));
+        }
+        Cow::Owned(self.colour(line, 0, pos, &[]))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{HINT}{hint}{RESET}"))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        // the colours and the matching brackets depend on the whole line, and on the cursor
+        true
+    }
+}
+
+impl Validator for YarerHelper<'_> {}
+
+impl Helper for YarerHelper<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colour() {
+        let session = Session::init();
+        session.set("x", 1);
+        let helper = YarerHelper::new(&session);
+        assert_eq!(
+            helper.colour("sqrt(x) + y", 0, 4, &[]),
+            format!("{FUNCTION}sqrt{RESET}{MATCHING_BRACKET}({RESET}x{MATCHING_BRACKET}){RESET} {OPERATOR}+{RESET} {UNKNOWN}y{RESET}")
+        );
+        assert_eq!(helper.colour("y = 2", 0, 9, &[]), format!("y {OPERATOR}={RESET} {NUMBER}2{RESET}"));
+        assert_eq!(helper.colour("x²", 0, 9, &[]), format!("x{OPERATOR}²{RESET}"));
+        session.process_recorded("x").resolve().unwrap();
+        assert_eq!(helper.colour("$1 $2", 0, 9, &[]), format!("$1 {UNKNOWN}$2{RESET}"));
+    }
+
+    #[test]
+    fn test_signature_and_candidates() {
+        let session = Session::init();
+        session.define_function("hyp", &["a", "b"], "sqrt(a^2 + b^2)").unwrap();
+        let helper = YarerHelper::new(&session);
+        assert_eq!(helper.signature("1 + sqrt(2 * (3"), Some("sqrt(x): Square root".to_string()));
+        assert_eq!(helper.signature("max(1, 2, "), Some("max(x1, ...): The largest of the values".to_string()));
+        assert_eq!(helper.signature("hyp(3, "), Some("hyp(a, b) = sqrt(a^2 + b^2)".to_string()));
+        assert_eq!(helper.signature("sqrt(2) + "), None);
+
+        let names: Vec<String> = helper.candidates("H").into_iter().map(|p| p.replacement).collect();
+        assert_eq!(names, ["hyp(", "hypot("]);
+    }
+}
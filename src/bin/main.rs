@@ -1,14 +1,25 @@
-use std::path::PathBuf;
-
 use clap::Parser;
 use rustyline::error::ReadlineError;
-use rustyline::{DefaultEditor, Result};
+use rustyline::history::DefaultHistory;
+use rustyline::{CompletionType, Config, Editor, Result};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
+use yarer::error::YarerError;
+use yarer::parser::Parser as ExprParser;
 use yarer::rpn_resolver::*;
 use yarer::session::*;
+use yarer::format::{Notation, NumberFormat};
 
 use log::debug;
 
+mod commands;
+mod helper;
+
+use helper::YarerHelper;
+
 static VERSION: &str = env!("CARGO_PKG_VERSION");
 static HISTORY_FILE: &str = ".yarer_history";
 
@@ -25,6 +36,42 @@ static HISTORY_FILE: &str = ".yarer_history";
 struct Cli {
     #[arg(short, long)]
     quiet: bool,
+
+    /// Computes the math functions and constants with N significant digits, up to 1000
+    #[arg(short, long, value_name = "N")]
+    precision: Option<u64>,
+
+    /// Writes the results as 'auto', 'fraction', 'fixed:N', 'sig:N', 'sci:N' or 'eng:N'
+    #[arg(short, long, value_name = "FORMAT")]
+    format: Option<Notation>,
+
+    /// Separates the thousands of the results with commas
+    #[arg(short, long)]
+    group: bool,
+
+    /// Evaluates the undefined variables as zero, instead of reporting an error
+    #[arg(short, long)]
+    lenient: bool,
+
+    /// Defines the imaginary unit 'i', and allows complex results as sqrt(-1)
+    #[arg(short, long)]
+    complex: bool,
+
+    /// Requires a '*' for every multiplication, instead of accepting '2x' or '3(4+5)'
+    #[arg(short = 'x', long)]
+    explicit: bool,
+
+    /// Restores the session from FILE at start, and saves it there on exit
+    #[arg(short, long, value_name = "FILE")]
+    session: Option<PathBuf>,
+
+    /// Evaluates EXPR and exits, instead of starting the REPL. It can be repeated
+    #[arg(short, long = "eval", value_name = "EXPR", allow_hyphen_values = true)]
+    expressions: Vec<String>,
+
+    /// Runs the statements of a script file, one per line, and exits
+    #[arg(value_name = "SCRIPT")]
+    script: Option<PathBuf>,
 }
 
 /**
@@ -51,10 +98,87 @@ The internal flow is conceptually straightforward:
      println!("The result of {} is {}", exp, result);
  ```
 */
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
     env_logger::init();
 
+    let session = Session::init();
+    if let Err(e) = session.set_precision(cli.precision) {
+        eprintln!("Error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    session.set_strict(!cli.lenient);
+    session.set_complex(cli.complex);
+    session.set_implicit(!cli.explicit);
+    session.set_number_format(NumberFormat {
+        notation: cli.format.unwrap_or_default(),
+        separator: cli.group.then_some(','),
+    });
+    if let Some(path) = cli.session.as_ref().filter(|p| p.exists()) {
+        if let Err(e) = session.load(path) {
+            eprintln!("Error: cannot load the session from {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let success = if !cli.expressions.is_empty() {
+        run_lines(&session, &cli, cli.expressions.iter().cloned())
+    } else if let Some(script) = &cli.script {
+        match fs::read_to_string(script) {
+            Ok(text) => run_lines(&session, &cli, text.lines().map(String::from)),
+            Err(e) => {
+                eprintln!("Error: cannot read {}: {}", script.display(), e);
+                false
+            }
+        }
+    } else if !io::stdin().is_terminal() {
+        run_lines(&session, &cli, io::stdin().lines().map_while(io::Result::ok))
+    } else {
+        match repl(&session, &cli) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                false
+            }
+        }
+    };
+
+    if let Some(path) = &cli.session {
+        if let Err(e) = session.save(path) {
+            eprintln!("Error: cannot save the session to {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Executes the lines of a script, of the standard input or of the `-e` arguments, without
+/// any prompt. Blank lines and the lines starting with '#' are skipped, and `quit` stops.
+/// Returns false at the first line that fails
+///
+fn run_lines(session: &Session, cli: &Cli, lines: impl Iterator<Item = String>) -> bool {
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.eq_ignore_ascii_case("quit") {
+            break;
+        }
+        if !execute(session, line, cli.session.as_ref()) {
+            return false;
+        }
+    }
+    true
+}
+
+/// The interactive loop, with the line history, the completion and the highlighting
+///
+fn repl(session: &Session, cli: &Cli) -> Result<()> {
     if !cli.quiet {
         println!(
             "Yarer v.{} - Yet Another Rust Expression Resolver.",
@@ -63,18 +187,21 @@ fn main() -> Result<()> {
         println!("License MIT OR Apache-2.0");
     }
 
-    let mut rl = DefaultEditor::new()?;
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut rl: Editor<YarerHelper, DefaultHistory> = Editor::with_config(config)?;
+    rl.set_helper(Some(YarerHelper::new(session)));
     let local_history = dirs::config_dir()
-        .unwrap_or(PathBuf::default())
+        .unwrap_or_default()
         .join(HISTORY_FILE);
     let local_history = local_history.as_os_str().to_str().unwrap_or(HISTORY_FILE);
     debug!("Local history file: '{}'", local_history);
 
     let _ = rl.load_history(local_history);
 
-    let session = Session::init();
     loop {
-        let readline = rl.readline("> ");
+        // the number the next result will be referenced with, as in '$4'
+        let prompt = format!("[${}]> ", session.results().len() + 1);
+        let readline = rl.readline(&prompt);
 
         match readline {
             Ok(line) => {
@@ -86,20 +213,14 @@ fn main() -> Result<()> {
                 }
 
                 let _ = rl.add_history_entry(line.as_str());
-
-                let mut resolver: RpnResolver = session.process(&line);
-
-                match resolver.resolve() {
-                    Ok(value) => println!("{}", value),
-                    Err(e) => println!("Error: {}", e),
-                }
+                execute(session, &line, cli.session.as_ref());
             }
             Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
                 println!("quit");
                 break;
             }
             Err(err) => {
-                println!("Error: {:?}", err);
+                eprintln!("Error: {:?}", err);
                 break;
             }
         }
@@ -107,3 +228,69 @@ fn main() -> Result<()> {
     let _ = rl.save_history(local_history);
     Ok(())
 }
+
+/// Executes a line: a command, a cell binding, a function definition or an expression,
+/// whose result is printed. Returns false if there's an error
+///
+fn execute(session: &Session, line: &str, session_file: Option<&PathBuf>) -> bool {
+    if let Some(command) = line.trim().strip_prefix(':') {
+        return commands::run(session, command, session_file);
+    }
+
+    if let Some((name, formula)) = ExprParser::parse_binding(line) {
+        return report(session.bind(name, formula));
+    }
+
+    if let Some((name, params, body)) = ExprParser::parse_definition(line) {
+        return report(session.define_function(name, &params, body));
+    }
+
+    let mut resolver: RpnResolver = session.process_recorded(line);
+    if !resolver.parse_errors().is_empty() {
+        print_errors(line, resolver.parse_errors());
+        return false;
+    }
+
+    match resolver.resolve() {
+        Ok(value) => {
+            println!("{}", session.format_number(&value));
+            true
+        }
+        Err(e) => {
+            print_errors(line, &[e]);
+            false
+        }
+    }
+}
+
+/// Prints the error of a definition, if any. Returns false if there's an error
+///
+fn report(result: std::result::Result<(), YarerError>) -> bool {
+    if let Err(e) = &result {
+        eprintln!("Error: {}", e);
+    }
+    result.is_ok()
+}
+
+/// Prints the input line with a caret under each error, followed by the error messages
+///
+fn print_errors(line: &str, errors: &[YarerError]) {
+    let mut marker = String::new();
+    for e in errors {
+        let span = e.span();
+        // the columns are counted in chars, not in bytes
+        let start = line.get(..span.start).map_or(0, |s| s.chars().count());
+        let width = line.get(span.start..span.end).map_or(1, |s| s.chars().count().max(1));
+        if start < marker.chars().count() {
+            continue; // overlapping spans
+        }
+        let padding = start - marker.chars().count();
+        marker.push_str(&" ".repeat(padding));
+        marker.push_str(&"^".repeat(width));
+    }
+    eprintln!("{}", line);
+    eprintln!("{}", marker);
+    for e in errors {
+        eprintln!("Error: {}", e);
+    }
+}
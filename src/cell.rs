@@ -0,0 +1,261 @@
+use crate::{
+    compiled::CompiledExpression,
+    error::{Span, YarerError},
+    function::Function,
+    rpn_resolver::RpnResolver,
+    shared::{read, Shared},
+    token::{Number, Token},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A [`Cell`] is a variable bound to a formula, i.e. `total := price * qty`:
+/// its value is recomputed every time one of its inputs changes
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cell {
+    /// The expression computing the value of the variable
+    pub formula: CompiledExpression,
+    /// The variables and constants read by the formula and, once bound in a session,
+    /// by the bodies of the user functions it calls
+    pub inputs: BTreeSet<String>,
+    /// The functions called by the formula: the cell is recomputed when one of them is redefined
+    pub functions: BTreeSet<String>,
+}
+
+impl Cell {
+    /// Compiles a new [`Cell`] from its formula
+    ///
+    /// # Errors
+    ///
+    /// the first syntax error of the formula, or [`YarerError::InvalidDefinition`]
+    /// if the formula assigns a variable
+    ///
+    pub fn new(name: &str, formula: &str) -> Result<Cell, YarerError> {
+        Self::new_with(name, formula, true, false)
+    }
+
+    /// Same as [`Cell::new`], but the implicit multiplications in the formula ('2x')
+    /// are only accepted if `implicit` is true, and the imaginary unit 'i' is read
+    /// as a constant if `complex` is true
+    ///
+    /// # Errors
+    ///
+    /// the first syntax error of the formula, or [`YarerError::InvalidDefinition`]
+    /// if the formula assigns a variable
+    ///
+    pub fn new_with(name: &str, formula: &str, implicit: bool, complex: bool) -> Result<Cell, YarerError> {
+        let formula = CompiledExpression::compile_with(formula, implicit).map_err(|mut errors| errors.remove(0))?;
+        Self::from_compiled(name, formula, complex)
+    }
+
+    /// Builds a new [`Cell`] from its formula, already compiled
+    ///
+    pub(crate) fn from_compiled(name: &str, formula: CompiledExpression, complex: bool) -> Result<Cell, YarerError> {
+        let dependencies = formula.dependencies_with(complex);
+        if !dependencies.assigned.is_empty() {
+            return Err(YarerError::InvalidDefinition {
+                name: name.to_string(),
+                span: Span::default(),
+            });
+        }
+        let inputs = dependencies
+            .variables
+            .into_iter()
+            .chain(dependencies.constants)
+            .collect();
+        Ok(Cell {
+            formula,
+            inputs,
+            functions: dependencies.functions,
+        })
+    }
+
+    /// Sets the inputs of the cell to the variables and constants read by its formula and by the
+    /// bodies of the user functions it calls, directly or through other user functions, except
+    /// their parameters: the cell is recomputed when any of them changes
+    ///
+    pub(crate) fn update_inputs(&mut self, functions: &HashMap<String, Function>, complex: bool) {
+        let dependencies = self.formula.dependencies_with(complex);
+        let mut inputs: BTreeSet<String> = dependencies
+            .variables
+            .into_iter()
+            .chain(dependencies.constants)
+            .collect();
+        let mut called: Vec<String> = self.functions.iter().cloned().collect();
+        let mut visited = BTreeSet::new();
+        while let Some(name) = called.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let Some(Function::User(fun)) = functions.get(&name) else {
+                continue;
+            };
+            let body = fun.body.dependencies_with(complex);
+            inputs.extend(
+                body.variables
+                    .into_iter()
+                    .chain(body.constants)
+                    .filter(|input| !fun.params.contains(input)),
+            );
+            called.extend(body.functions);
+        }
+        self.inputs = inputs;
+    }
+}
+
+/// The cells of a [`crate::session::Session`], by the name of their variable
+///
+pub(crate) type Cells = Shared<BTreeMap<String, Cell>>;
+
+/// Finds out if binding `name` to a formula reading `inputs` would make a cycle, and returns it:
+/// i.e. `["a", "b", "a"]` if `b` reads `a`, and `a` would read `b`
+///
+pub(crate) fn find_cycle(
+    cells: &BTreeMap<String, Cell>,
+    name: &str,
+    inputs: &BTreeSet<String>,
+) -> Option<Vec<String>> {
+    fn visit(
+        cells: &BTreeMap<String, Cell>,
+        name: &str,
+        path: &mut Vec<String>,
+        visited: &mut BTreeSet<String>,
+    ) -> bool {
+        let current = path.last().cloned().unwrap_or_default();
+        if current == name {
+            return true;
+        }
+        if !visited.insert(current.clone()) {
+            return false;
+        }
+        for input in cells.get(&current).map(|c| &c.inputs).into_iter().flatten() {
+            path.push(input.clone());
+            if visit(cells, name, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+
+    let mut visited = BTreeSet::new();
+    for input in inputs {
+        let mut path = vec![name.to_string(), input.clone()];
+        if visit(cells, name, &mut path, &mut visited) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// The cells that depend on `name`, a variable or a function, directly or through other cells,
+/// sorted so that every cell comes after its inputs
+///
+pub(crate) fn dependents(cells: &BTreeMap<String, Cell>, name: &str) -> Vec<String> {
+    fn visit(
+        cells: &BTreeMap<String, Cell>,
+        cell: &str,
+        affected: &BTreeSet<&str>,
+        sorted: &mut Vec<String>,
+    ) {
+        if sorted.iter().any(|s| s == cell) {
+            return;
+        }
+        for input in cells.get(cell).map(|c| &c.inputs).into_iter().flatten() {
+            if affected.contains(input.as_str()) {
+                visit(cells, input, affected, sorted);
+            }
+        }
+        sorted.push(cell.to_string());
+    }
+
+    let mut affected: BTreeSet<&str> = cells
+        .iter()
+        .filter(|(_, c)| c.functions.contains(name))
+        .map(|(cell, _)| cell.as_str())
+        .collect();
+    let mut changed: Vec<&str> = affected.iter().copied().chain([name]).collect();
+    while let Some(var) = changed.pop() {
+        for (cell, c) in cells {
+            if c.inputs.contains(var) && affected.insert(cell) {
+                changed.push(cell);
+            }
+        }
+    }
+    let mut sorted = Vec::new();
+    for cell in &affected {
+        visit(cells, cell, &affected, &mut sorted);
+    }
+    sorted
+}
+
+/// Recomputes the cells that depend on the variable or the function `name`, after it has changed,
+/// in the heap already locked by the caller, with the resolvers built by `resolver`.
+/// A cell whose formula can't be evaluated (i.e. a division by zero) is left undefined
+///
+pub(crate) fn recompute(
+    name: &str,
+    cells: &Cells,
+    heap: &mut HashMap<String, Number>,
+    resolver: impl for<'r> Fn(&'r [(Token<'static>, Span)]) -> RpnResolver<'r>,
+) {
+    let formulas: Vec<(String, CompiledExpression)> = {
+        let cells = read(cells);
+        dependents(&cells, name)
+            .into_iter()
+            .filter_map(|cell| Some((cell.clone(), cells.get(&cell)?.formula.clone())))
+            .collect()
+    };
+    for (cell, formula) in formulas {
+        match resolver(formula.rpn()).resolve_in(heap) {
+            Ok(value) => heap.insert(cell, value),
+            Err(_) => heap.remove(&cell),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::UserFunction;
+
+    fn cells(formulas: &[(&str, &str)]) -> BTreeMap<String, Cell> {
+        formulas
+            .iter()
+            .map(|(name, formula)| ((*name).to_string(), Cell::new(name, formula).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_dependents() {
+        let cells = cells(&[("total", "net + tax"), ("tax", "net * vat"), ("net", "price * qty")]);
+        assert_eq!(dependents(&cells, "price"), ["net", "tax", "total"]);
+        assert_eq!(dependents(&cells, "vat"), ["tax", "total"]);
+        assert!(dependents(&cells, "total").is_empty());
+
+        let cells = self::cells(&[("d", "dbl(q) + 1"), ("e", "d * 2"), ("f", "max(q, 1)")]);
+        assert_eq!(dependents(&cells, "dbl"), ["d", "e"]);
+        assert_eq!(dependents(&cells, "q"), ["d", "e", "f"]);
+    }
+
+    #[test]
+    fn test_update_inputs() {
+        let functions = HashMap::from([
+            ("f".to_string(), Function::User(UserFunction::new(&["x"], "x + g(k)").unwrap())),
+            ("g".to_string(), Function::User(UserFunction::new(&["y"], "y * m + f(y)").unwrap())),
+        ]);
+        let mut cell = Cell::new("c", "f(a) + pi").unwrap();
+        cell.update_inputs(&functions, false);
+        assert_eq!(cell.inputs, BTreeSet::from(["a", "k", "m", "pi"].map(String::from)));
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let cells = cells(&[("b", "a + 1"), ("c", "b * 2")]);
+        let inputs = BTreeSet::from(["c".to_string()]);
+        assert_eq!(find_cycle(&cells, "a", &inputs), Some(vec!["a".into(), "c".into(), "b".into(), "a".into()]));
+        let inputs = BTreeSet::from(["x".to_string()]);
+        assert_eq!(find_cycle(&cells, "a", &inputs), None);
+        assert!(Cell::new("a", "b = 1").is_err());
+    }
+}
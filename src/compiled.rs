@@ -0,0 +1,212 @@
+use crate::{
+    ast::{Dependencies, Expr},
+    error::{Span, YarerError},
+    parser::Parser,
+    session::Session,
+    token::{Number, Token},
+};
+use std::fmt::Display;
+
+/// A [`CompiledExpression`] is a math expression already parsed and converted to RPN.
+///
+/// Unlike an [`crate::rpn_resolver::RpnResolver`] it owns all its tokens, so it borrows neither the source
+/// string nor a [`Session`]: it can be stored, cloned, shared between threads and
+/// resolved many times against any [`Session`]
+///
+/// # Examples
+///
+/// ```
+/// #    use yarer::{compiled::CompiledExpression, session::Session};
+///
+///      let compiled = CompiledExpression::compile("x^2 + 1").unwrap();
+///
+///      let session = Session::init();
+///      session.set("x", 3);
+///      assert_eq!(compiled.resolve(&session).unwrap().to_string(), "10");
+///      session.set("x", 4);
+///      assert_eq!(compiled.resolve(&session).unwrap().to_string(), "17");
+///  ```
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompiledExpression {
+    source: String,
+    expr: Expr,
+    rpn_expr: Vec<(Token<'static>, Span)>,
+}
+
+impl CompiledExpression {
+    /// Parses and compiles a math expression, accepting the implicit multiplications ('2x')
+    ///
+    /// # Errors
+    ///
+    /// all the syntax errors found in the expression, sorted by position
+    ///
+    pub fn compile(expr: &str) -> Result<CompiledExpression, Vec<YarerError>> {
+        Self::compile_with(expr, true)
+    }
+
+    /// Same as [`CompiledExpression::compile`], but the implicit multiplications are only
+    /// accepted if `implicit` is true, as in a [`Session`] with [`Session::is_implicit`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::{compiled::CompiledExpression, session::Session};
+    ///
+    ///      let session = Session::init();
+    ///      session.set_implicit(false);
+    ///      assert!(CompiledExpression::compile_with("2x", session.is_implicit()).is_err());
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// all the syntax errors found in the expression, sorted by position
+    ///
+    pub fn compile_with(expr: &str, implicit: bool) -> Result<CompiledExpression, Vec<YarerError>> {
+        Self::compile_calling(expr, implicit, |_| true)
+    }
+
+    /// Same as [`CompiledExpression::compile_with`], but a name followed by an open bracket
+    /// is a call only if `is_function` accepts it. See [`Parser::parse_expr_calling`]
+    ///
+    pub(crate) fn compile_calling(
+        expr: &str,
+        implicit: bool,
+        is_function: impl Fn(&str) -> bool,
+    ) -> Result<CompiledExpression, Vec<YarerError>> {
+        let tree = Parser::parse_expr_calling(expr, implicit, is_function)?;
+        Ok(CompiledExpression {
+            source: expr.to_string(),
+            rpn_expr: tree.to_rpn(),
+            expr: tree,
+        })
+    }
+
+    /// Compiles an [`Expr`] tree, i.e. one rewritten by a [`crate::ast::Fold`].
+    /// Its source is the tree displayed as an infix expression
+    ///
+    #[must_use]
+    pub fn from_expr(expr: Expr) -> CompiledExpression {
+        CompiledExpression {
+            source: expr.to_string(),
+            rpn_expr: expr.to_rpn(),
+            expr,
+        }
+    }
+
+    /// Evaluates the expression with the variables, settings and functions of a [`Session`]
+    ///
+    /// # Errors
+    ///
+    /// a [`YarerError`] if the expression is malformed or can't be evaluated (i.e. a division by zero)
+    ///
+    pub fn resolve(&self, session: &Session) -> Result<Number, YarerError> {
+        session.resolver(&self.rpn_expr).resolve()
+    }
+
+    /// The source of the expression
+    ///
+    #[must_use]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The expression converted to RPN
+    ///
+    pub(crate) fn rpn(&self) -> &[(Token<'static>, Span)] {
+        &self.rpn_expr
+    }
+
+    /// The [`Expr`] tree of the expression
+    ///
+    #[must_use]
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// The free variables, the constants and the functions the expression depends on,
+    /// and the variables it assigns. See [`Expr::dependencies`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::compiled::CompiledExpression;
+    ///
+    ///      let compiled = CompiledExpression::compile("total = price * qty * (1 + vat)").unwrap();
+    ///      let inputs: Vec<String> = compiled.dependencies().variables.into_iter().collect();
+    ///      assert_eq!(inputs, ["price", "qty", "vat"]);
+    ///  ```
+    ///
+    #[must_use]
+    pub fn dependencies(&self) -> Dependencies {
+        self.expr.dependencies()
+    }
+
+    /// Same as [`CompiledExpression::dependencies`], but the imaginary unit 'i' is a constant
+    /// if `complex` is true. See [`Expr::dependencies_with`]
+    ///
+    #[must_use]
+    pub fn dependencies_with(&self, complex: bool) -> Dependencies {
+        self.expr.dependencies_with(complex)
+    }
+}
+
+impl Display for CompiledExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_compiled_expression() {
+        assert_send_sync::<CompiledExpression>();
+
+        let compiled = {
+            let line = String::from("hyp(a, 4) + 2 * a");
+            CompiledExpression::compile(&line).unwrap()
+        };
+        assert_eq!(compiled.to_string(), "hyp(a, 4) + 2 * a");
+
+        let session = Session::init();
+        session.define_function("hyp", &["x", "y"], "sqrt(x^2 + y^2)").unwrap();
+        session.set("a", 3);
+        assert_eq!(compiled.resolve(&session).unwrap().to_string(), "11");
+
+        let other = Session::init();
+        other.set("a", 3);
+        assert!(matches!(
+            compiled.clone().resolve(&other),
+            Err(YarerError::UnknownFunction { .. })
+        ));
+
+        assert_eq!(
+            CompiledExpression::compile("1 + (2").unwrap_err(),
+            vec![YarerError::UnbalancedBracket { span: Span::new(4, 5) }]
+        );
+    }
+
+    #[test]
+    fn test_compile_with_implicit_mode() {
+        let session = Session::init();
+        session.set("x", 3);
+        let implicit = CompiledExpression::compile_with("2x", true).unwrap();
+        assert_eq!(implicit, CompiledExpression::compile("2x").unwrap());
+        assert_eq!(implicit.resolve(&session).unwrap().to_string(), "6");
+        assert_eq!(
+            CompiledExpression::compile_with("2x", false).unwrap_err(),
+            vec![YarerError::UnexpectedToken { token: "x".to_string(), span: Span::new(1, 2) }]
+        );
+
+        // the cells and the functions of a session follow its mode too
+        session.set_implicit(false);
+        assert!(session.bind("y", "2x").is_err());
+        assert!(session.define_function("f", &["a"], "2a").is_err());
+        assert!(session.bind("y", "2 * x").is_ok());
+    }
+}
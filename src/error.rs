@@ -0,0 +1,245 @@
+use crate::function::Arity;
+use std::fmt::Display;
+use thiserror::Error;
+
+/// The [Span] of a token: the range of bytes `start..end` it occupies in the source expression
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    /// Offset of the first byte of the token
+    pub start: usize,
+    /// Offset of the byte after the end of the token
+    pub end: usize,
+}
+
+impl Span {
+    /// Builds a new [Span] from its byte offsets
+    ///
+    #[must_use]
+    pub const fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// The [`YarerError`] enum. Every error raised while parsing or evaluating an expression,
+/// each one with the [Span] of the offending token in the source expression
+///
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum YarerError {
+    /// Division (or negative power) of zero ('1/0')
+    #[error("Runtime error: Divide by zero.")]
+    DivisionByZero {
+        /// span of the operator
+        span: Span,
+    },
+    /// A variable that has never been defined
+    #[error("Runtime error: Unknown variable '{name}'.")]
+    UnknownVariable {
+        /// name of the variable
+        name: String,
+        /// span of the variable
+        span: Span,
+    },
+    /// A function that has never been defined
+    #[error("Runtime error: Unknown function '{name}'.")]
+    UnknownFunction {
+        /// name of the function
+        name: String,
+        /// span of the function
+        span: Span,
+    },
+    /// Too many nested calls of user functions ('f(x) = f(x)')
+    #[error("Runtime error: Recursion limit exceeded in function {function}.")]
+    RecursionLimit {
+        /// name of the function
+        function: String,
+        /// span of the function
+        span: Span,
+    },
+    /// A function or cell definition with a reserved name, invalid parameters ('sin(x) = x'),
+    /// or an assignment in the formula of a cell ('a := b = 1')
+    #[error("Syntax error: Invalid definition of {name}.")]
+    InvalidDefinition {
+        /// name of the function
+        name: String,
+        /// span of the definition
+        span: Span,
+    },
+    /// A cell whose formula depends on itself, directly or through other cells ('a := b + 1', 'b := a')
+    #[error("Runtime error: Cyclic definition {}.", cycle.join(" -> "))]
+    CyclicDefinition {
+        /// the names of the cells in the cycle, from the defined one back to it
+        cycle: Vec<String>,
+        /// span of the definition
+        span: Span,
+    },
+    /// A function called with the wrong number of arguments ('max(1)')
+    #[error("Runtime error: Function {function} expects {expected} argument(s), found {found}.")]
+    ArityMismatch {
+        /// name of the function
+        function: String,
+        /// number of the expected arguments
+        expected: Arity,
+        /// number of the given arguments
+        found: usize,
+        /// span of the function
+        span: Span,
+    },
+    /// An error raised by a native function registered in a session
+    #[error("Runtime error: Function {function} failed: {message}.")]
+    FunctionError {
+        /// name of the function
+        function: String,
+        /// the description of the error
+        message: String,
+        /// span of the function
+        span: Span,
+    },
+    /// An argument outside of the domain of a function or an operator ('ln(0)', '(-1)!')
+    #[error("Runtime error: Argument out of the domain of {function}.")]
+    DomainError {
+        /// name of the function or operator
+        function: String,
+        /// span of the function or operator
+        span: Span,
+    },
+    /// A result too large to be represented ('10^10^10')
+    #[error("Runtime error: Numeric overflow.")]
+    Overflow {
+        /// span of the function or operator
+        span: Span,
+    },
+    /// A complex number where an ordering is needed ('max(i, 1)')
+    #[error("Runtime error: Complex numbers can't be ordered.")]
+    NotOrdered {
+        /// span of the function or operator
+        span: Span,
+    },
+    /// A precision mode with more significant digits than [`crate::session::MAX_PRECISION`]
+    #[error("Runtime error: Precision of {digits} digits exceeds the maximum of {max}.")]
+    PrecisionLimit {
+        /// the requested number of significant digits
+        digits: u64,
+        /// the maximum number of significant digits
+        max: u64,
+        /// span of the request, if any
+        span: Span,
+    },
+    /// An expression nested too deeply to be walked ('-----...1')
+    #[error("Syntax error: Expression nested too deeply.")]
+    NestingLimit {
+        /// span of the innermost operator or function over the limit
+        span: Span,
+    },
+    /// An open bracket without the close one, or vice versa ('(1+2')
+    #[error("Syntax error: Unbalanced bracket.")]
+    UnbalancedBracket {
+        /// span of the bracket
+        span: Span,
+    },
+    /// A character that isn't part of any token ('2 $ 3')
+    #[error("Syntax error: Unknown character '{character}'.")]
+    UnknownCharacter {
+        /// the unknown character
+        character: char,
+        /// span of the character
+        span: Span,
+    },
+    /// A token that can't appear in its position ('1+*2')
+    #[error("Syntax error: Unexpected token '{token}'.")]
+    UnexpectedToken {
+        /// the text of the token
+        token: String,
+        /// span of the token
+        span: Span,
+    },
+    /// An assignment without a variable on its left side ('1=2')
+    #[error("Runtime error: No variable has been defined for assignment.")]
+    InvalidAssignment {
+        /// span of the assignment operator
+        span: Span,
+    },
+    /// An expression that doesn't produce any value ('()')
+    #[error("Runtime Error: The mathematical expression is malformed.")]
+    Malformed {
+        /// span of the whole expression
+        span: Span,
+    },
+}
+
+impl YarerError {
+    /// Returns the [Span] of the token that caused the error
+    ///
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            YarerError::DivisionByZero { span }
+            | YarerError::UnknownVariable { span, .. }
+            | YarerError::UnknownFunction { span, .. }
+            | YarerError::RecursionLimit { span, .. }
+            | YarerError::InvalidDefinition { span, .. }
+            | YarerError::CyclicDefinition { span, .. }
+            | YarerError::ArityMismatch { span, .. }
+            | YarerError::FunctionError { span, .. }
+            | YarerError::DomainError { span, .. }
+            | YarerError::Overflow { span }
+            | YarerError::NotOrdered { span }
+            | YarerError::PrecisionLimit { span, .. }
+            | YarerError::NestingLimit { span }
+            | YarerError::UnbalancedBracket { span }
+            | YarerError::UnknownCharacter { span, .. }
+            | YarerError::UnexpectedToken { span, .. }
+            | YarerError::InvalidAssignment { span }
+            | YarerError::Malformed { span } => *span,
+        }
+    }
+
+    /// Returns the same error, moved to another [Span]
+    ///
+    #[must_use]
+    pub fn with_span(mut self, new_span: Span) -> YarerError {
+        match &mut self {
+            YarerError::DivisionByZero { span }
+            | YarerError::UnknownVariable { span, .. }
+            | YarerError::UnknownFunction { span, .. }
+            | YarerError::RecursionLimit { span, .. }
+            | YarerError::InvalidDefinition { span, .. }
+            | YarerError::CyclicDefinition { span, .. }
+            | YarerError::ArityMismatch { span, .. }
+            | YarerError::FunctionError { span, .. }
+            | YarerError::DomainError { span, .. }
+            | YarerError::Overflow { span }
+            | YarerError::NotOrdered { span }
+            | YarerError::PrecisionLimit { span, .. }
+            | YarerError::NestingLimit { span }
+            | YarerError::UnbalancedBracket { span }
+            | YarerError::UnknownCharacter { span, .. }
+            | YarerError::UnexpectedToken { span, .. }
+            | YarerError::InvalidAssignment { span }
+            | YarerError::Malformed { span } => *span = new_span,
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_span() {
+        let err = YarerError::UnknownVariable {
+            name: "pirce".to_string(),
+            span: Span::new(0, 5),
+        };
+        assert_eq!(err.span(), Span::new(0, 5));
+        assert_eq!(err.to_string(), "Runtime error: Unknown variable 'pirce'.");
+        assert_eq!(err.with_span(Span::new(3, 4)).span(), Span::new(3, 4));
+    }
+}
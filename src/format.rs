@@ -0,0 +1,378 @@
+use crate::token::Number;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Pow, Signed, ToPrimitive, Zero};
+use std::{fmt::Display, str::FromStr};
+
+/// The significant digits of a [`Number`] that doesn't fit in an f64, in [`Notation::Auto`]
+///
+const AUTO_DIGITS: usize = 17;
+
+/// How the digits of a [`Number`] are written
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// Integers in full, decimals as the shortest f64 representation ('0.1'). It's the default
+    #[default]
+    Auto,
+    /// n digits after the decimal point: '3.14' for 'fixed:2'
+    Fixed(usize),
+    /// n significant digits, switching to the scientific notation for the very large or small
+    /// numbers, as `%g` does: '3.14159' for 'sig:6'
+    Significant(usize),
+    /// n significant digits and an exponent: '6.022e23' for 'sci:4'
+    Scientific(usize),
+    /// n significant digits and an exponent multiple of 3: '602.2e21' for 'eng:4'
+    Engineering(usize),
+    /// The exact fraction: '1/3'
+    Fraction,
+}
+
+/// The configuration used by [`Number::format`]
+///
+/// # Examples
+///
+/// ```
+/// #    use yarer::{format::{Notation, NumberFormat}, session::Session};
+///
+///      let session = Session::init();
+///      let result = session.process("1234567 / 3").resolve().unwrap();
+///      let format = NumberFormat::new(Notation::Fixed(2)).with_separator(',');
+///      assert_eq!(result.format(&format), "411,522.33");
+///      assert_eq!(result.format(&NumberFormat::new(Notation::Fraction)), "1234567/3");
+///  ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumberFormat {
+    /// How the digits are written
+    pub notation: Notation,
+    /// The character that separates the thousands of the integer part, if any
+    pub separator: Option<char>,
+}
+
+impl NumberFormat {
+    /// Builds a [`NumberFormat`] with a [`Notation`] and no thousands separator
+    ///
+    #[must_use]
+    pub const fn new(notation: Notation) -> NumberFormat {
+        NumberFormat {
+            notation,
+            separator: None,
+        }
+    }
+
+    /// Separates the thousands of the integer part with `separator`: '1,234,567'
+    ///
+    #[must_use]
+    pub const fn with_separator(self, separator: char) -> NumberFormat {
+        NumberFormat {
+            separator: Some(separator),
+            ..self
+        }
+    }
+
+    /// Inserts the thousands separator, if any, in the integer parts of a formatted number
+    /// (i.e. both the numerator and the denominator of a fraction)
+    ///
+    #[must_use]
+    pub fn grouped(&self, text: &str) -> String {
+        match self.separator {
+            Some(separator) => group(text, separator),
+            None => text.to_string(),
+        }
+    }
+}
+
+impl Number {
+    /// Formats the [Number] with a [`NumberFormat`]. All the notations but [`Notation::Auto`]
+    /// are computed on the exact rational value, so they work for any magnitude
+    ///
+    #[must_use]
+    pub fn format(&self, format: &NumberFormat) -> String {
+        let exact = |digits: fn(&BigRational, usize) -> String, n: usize| {
+            let value = BigRational::from(self.clone());
+            signed(&value, digits(&value.abs(), n))
+        };
+        let text = match (format.notation, self) {
+            (_, Number::ComplexNumber(c)) => {
+                // the separators are inserted in both parts at the end
+                let plain = NumberFormat::new(format.notation);
+                let part = |r: &BigRational| Number::from_rational(r.clone()).format(&plain);
+                join_complex((!c.re.is_zero()).then(|| part(&c.re)).as_deref(), &part(&c.im))
+            }
+            (Notation::Auto | Notation::Fraction, Number::NaturalNumber(v)) => v.to_string(),
+            (Notation::Auto, Number::DecimalNumber(v)) => match v.to_f64() {
+                // tiny numbers mustn't underflow to 0, and huge ones mustn't overflow to inf
+                Some(f) if f.is_finite() && (f != 0.0 || v.is_zero()) => f.to_string(),
+                _ => signed(v, general(&v.abs(), AUTO_DIGITS)),
+            },
+            (Notation::Fraction, Number::DecimalNumber(v)) => v.to_string(),
+            (Notation::Fixed(n), _) => exact(fixed, n),
+            (Notation::Significant(n), _) => exact(general, n),
+            (Notation::Scientific(n), _) => exact(|r, n| scientific(r, n, 1), n),
+            (Notation::Engineering(n), _) => exact(|r, n| scientific(r, n, 3), n),
+        };
+        format.grouped(&text)
+    }
+}
+
+impl Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notation::Auto => write!(f, "auto"),
+            Notation::Fixed(n) => write!(f, "fixed:{n}"),
+            Notation::Significant(n) => write!(f, "sig:{n}"),
+            Notation::Scientific(n) => write!(f, "sci:{n}"),
+            Notation::Engineering(n) => write!(f, "eng:{n}"),
+            Notation::Fraction => write!(f, "fraction"),
+        }
+    }
+}
+
+/// Parses a [`Notation`] written as its [Display]: 'auto', 'fraction', 'fixed:N', 'sig:N',
+/// 'sci:N' or 'eng:N'
+///
+impl FromStr for Notation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, digits) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let digits = || {
+            digits
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("'{name}' needs a number of digits, as in '{name}:4'"))
+        };
+        match name.to_lowercase().as_str() {
+            "auto" => Ok(Notation::Auto),
+            "fraction" => Ok(Notation::Fraction),
+            "fixed" => Ok(Notation::Fixed(digits()?)),
+            "sig" => Ok(Notation::Significant(digits()?)),
+            "sci" => Ok(Notation::Scientific(digits()?)),
+            "eng" => Ok(Notation::Engineering(digits()?)),
+            _ => Err(format!("unknown format '{s}'")),
+        }
+    }
+}
+
+/// Puts the minus sign in front of the formatted magnitude of `value`,
+/// unless it has been rounded to zero
+///
+fn signed(value: &BigRational, magnitude: String) -> String {
+    if value.is_negative() && magnitude.chars().any(|c| ('1'..='9').contains(&c)) {
+        format!("-{magnitude}")
+    } else {
+        magnitude
+    }
+}
+
+/// 10^n
+///
+fn pow10(n: u64) -> BigInt {
+    Pow::pow(BigInt::from(10), n)
+}
+
+/// `r` multiplied by 10^scale and rounded to an integer, half away from zero
+///
+fn scaled(r: &BigRational, scale: i64) -> BigInt {
+    let factor = BigRational::from_integer(pow10(scale.unsigned_abs()));
+    let r = if scale >= 0 { r * factor } else { r / factor };
+    r.round().to_integer()
+}
+
+/// The exponent of the most significant digit of a positive `r`: 2 for 123.4, -3 for 0.001
+///
+fn exponent(r: &BigRational) -> i64 {
+    let ten_pow = |e: i64| {
+        let p = BigRational::from_integer(pow10(e.unsigned_abs()));
+        if e >= 0 { p } else { p.recip() }
+    };
+    let bits = |n: &BigInt| i64::try_from(n.bits()).unwrap_or(i64::MAX);
+    // log10(2) is about 0.30103: this guess is off by one or two at most
+    let mut e = (bits(r.numer()) - bits(r.denom())) * 30103 / 100_000;
+    while r >= &ten_pow(e + 1) {
+        e += 1;
+    }
+    while r < &ten_pow(e) {
+        e -= 1;
+    }
+    e
+}
+
+/// The `n` significant digits of a non-negative `r` and the exponent of the first one,
+/// i.e. ("123", 4) for 12345.6 with n = 3
+///
+fn significant(r: &BigRational, n: usize) -> (String, i64) {
+    let n = n.max(1);
+    if r.is_zero() {
+        return ("0".repeat(n), 0);
+    }
+    let digits = i64::try_from(n).unwrap_or(i64::MAX);
+    let mut e = exponent(r);
+    let mut s = scaled(r, digits - 1 - e);
+    // rounding can carry into a new digit, as 9.99 to 10.0
+    if s >= pow10(n as u64) {
+        e += 1;
+        s = scaled(r, digits - 1 - e);
+    }
+    (s.to_string(), e)
+}
+
+/// Puts the decimal point after the first `int_len` digits, adding the zeros needed
+///
+fn place_point(digits: &str, int_len: i64) -> String {
+    match usize::try_from(int_len) {
+        Ok(len) if len >= digits.len() => format!("{digits}{}", "0".repeat(len - digits.len())),
+        Ok(0) | Err(_) => format!("0.{}{digits}", "0".repeat(usize::try_from(-int_len).unwrap_or_default())),
+        Ok(len) => format!("{}.{}", &digits[..len], &digits[len..]),
+    }
+}
+
+/// Removes the zeros at the end of the decimal part, and the point if nothing's left
+///
+fn trim_zeros(text: &str) -> &str {
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        text
+    }
+}
+
+/// A non-negative `r` with `n` digits after the decimal point
+///
+fn fixed(r: &BigRational, n: usize) -> String {
+    let digits = scaled(r, i64::try_from(n).unwrap_or(i64::MAX)).to_string();
+    let digits = format!("{digits:0>width$}", width = n.saturating_add(1));
+    place_point(&digits, i64::try_from(digits.len() - n).unwrap_or_default())
+}
+
+/// A non-negative `r` with `n` significant digits and an exponent multiple of `step`:
+/// 1 for the scientific notation, 3 for the engineering one
+///
+fn scientific(r: &BigRational, n: usize, step: i64) -> String {
+    let (digits, e) = significant(r, n);
+    let shift = e.rem_euclid(step);
+    let int_len = usize::try_from(shift).unwrap_or_default() + 1;
+    let digits = format!("{digits:0<int_len$}");
+    format!("{}e{}", place_point(&digits, shift + 1), e - shift)
+}
+
+/// A non-negative `r` with up to `n` significant digits, in the scientific notation only if
+/// its exponent is less than -4 or at least `n`, as `%g` does
+///
+fn general(r: &BigRational, n: usize) -> String {
+    let (digits, e) = significant(r, n);
+    if e < -4 || e >= i64::try_from(n.max(1)).unwrap_or(i64::MAX) {
+        let mantissa = place_point(&digits, 1);
+        format!("{}e{e}", trim_zeros(&mantissa))
+    } else {
+        trim_zeros(&place_point(&digits, e + 1)).to_string()
+    }
+}
+
+/// Joins the formatted parts of a complex number, without the real one if it's 0: '1+2i', '-2.5i', '3-i'
+///
+pub(crate) fn join_complex(re: Option<&str>, im: &str) -> String {
+    let im = match im {
+        "1" => "",
+        "-1" => "-",
+        im => im,
+    };
+    match re {
+        Some(re) if im.starts_with('-') => format!("{re}{im}i"),
+        Some(re) => format!("{re}+{im}i"),
+        None => format!("{im}i"),
+    }
+}
+
+/// Inserts `separator` between the thousands of every integer part of `text`,
+/// that is every run of digits that doesn't follow a decimal point or an exponent
+///
+fn group(text: &str, separator: char) -> String {
+    let mut grouped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_digit()) {
+        let end = rest[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| start + i);
+        grouped.push_str(&rest[..start]);
+        let fractional = grouped.ends_with('.') || grouped.trim_end_matches(['-', '+']).ends_with('e');
+        let digits = &rest[start..end];
+        if fractional {
+            grouped.push_str(digits);
+        } else {
+            // the first group has from 1 to 3 digits, the others 3
+            let first = (digits.len() + 2) % 3 + 1;
+            grouped.push_str(&digits[..first]);
+            for i in (first..digits.len()).step_by(3) {
+                grouped.push(separator);
+                grouped.push_str(&digits[i..i + 3]);
+            }
+        }
+        rest = &rest[end..];
+    }
+    grouped.push_str(rest);
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(n: &Number, notation: &str) -> String {
+        n.format(&NumberFormat::new(notation.parse().unwrap()))
+    }
+
+    #[test]
+    fn test_notations() {
+        let third = Number::DecimalNumber(BigRational::new(BigInt::from(-1), BigInt::from(3)));
+        assert_eq!(format(&third, "auto"), "-0.3333333333333333");
+        assert_eq!(format(&third, "fixed:3"), "-0.333");
+        assert_eq!(format(&third, "fixed:0"), "0");
+        assert_eq!(format(&third, "sig:2"), "-0.33");
+        assert_eq!(format(&third, "sci:3"), "-3.33e-1");
+        assert_eq!(format(&third, "eng:3"), "-333e-3");
+        assert_eq!(format(&third, "fraction"), "-1/3");
+
+        let avogadro = Number::NaturalNumber(BigInt::from(602_214_076) * pow10(15));
+        assert_eq!(format(&avogadro, "auto"), "602214076000000000000000");
+        assert_eq!(format(&avogadro, "sig:4"), "6.022e23");
+        assert_eq!(format(&avogadro, "sci:2"), "6.0e23");
+        assert_eq!(format(&avogadro, "eng:4"), "602.2e21");
+        assert_eq!(format(&avogadro, "eng:1"), "600e21");
+        assert_eq!(format(&avogadro, "fixed:1"), "602214076000000000000000.0");
+
+        let nines = Number::DecimalNumber(BigRational::new(BigInt::from(9999), BigInt::from(1000)));
+        assert_eq!(format(&nines, "sig:3"), "10");
+        assert_eq!(format(&nines, "sci:3"), "1.00e1");
+        assert_eq!(format(&nines, "fixed:2"), "10.00");
+        assert_eq!(format(&Number::NaturalNumber(BigInt::zero()), "sci:3"), "0.00e0");
+
+        assert!("fixed".parse::<Notation>().is_err());
+        assert!("round:2".parse::<Notation>().is_err());
+    }
+
+    #[test]
+    fn test_huge_and_tiny() {
+        let huge = Number::DecimalNumber(BigRational::new(pow10(400) + BigInt::from(1), BigInt::from(3)));
+        assert_eq!(huge.to_string(), "3.3333333333333333e399");
+        let tiny = Number::DecimalNumber(BigRational::new(BigInt::from(2), pow10(400)));
+        assert_eq!(tiny.to_string(), "2e-400");
+        assert_eq!(format(&tiny, "fixed:2"), "0.00");
+    }
+
+    #[test]
+    fn test_grouping() {
+        let format = NumberFormat::new(Notation::Auto).with_separator(',');
+        assert_eq!(Number::NaturalNumber(BigInt::from(-1_234_567)).format(&format), "-1,234,567");
+        assert_eq!(Number::NaturalNumber(BigInt::from(123)).format(&format), "123");
+        let fraction = NumberFormat::new(Notation::Fraction).with_separator('_');
+        let n = Number::DecimalNumber(BigRational::new(BigInt::from(1_000_001), BigInt::from(1000)));
+        assert_eq!(n.format(&fraction), "1_000_001/1_000");
+        assert_eq!(NumberFormat::default().with_separator(' ').grouped("12345.678"), "12 345.678");
+        assert_eq!(NumberFormat::default().with_separator(',').grouped("-1234.5678e1234-5678i"), "-1,234.5678e1234-5,678i");
+        let long = NumberFormat::default().with_separator(',').grouped(&"9".repeat(3001));
+        assert_eq!((long.len(), long.matches(',').count()), (4001, 1000));
+        assert!(long.starts_with("9,999,"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    compiled::CompiledExpression,
+    error::YarerError,
+    shared::{Ptr, Shared},
+    token::Number,
+};
+use std::{collections::HashMap, fmt::Display};
+
+/// The maximum depth of nested calls of [`UserFunction`]s, before a recursion error
+///
+pub const CALL_DEPTH_LIMIT: usize = 100;
+
+/// The number of arguments accepted by a [`Function`]
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Arity {
+    /// Exactly n arguments
+    Exact(usize),
+    /// n or more arguments (variadic)
+    AtLeast(usize),
+}
+
+impl Arity {
+    /// Returns true if a function with this [`Arity`] can be called with `n` arguments
+    ///
+    #[must_use]
+    pub const fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(a) => n == a,
+            Arity::AtLeast(a) => n >= a,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{n}"),
+            Arity::AtLeast(n) => write!(f, "at least {n}"),
+        }
+    }
+}
+
+/// A [`UserFunction`] is a function defined by an expression of its parameters,
+/// i.e. `hyp(a, b) = sqrt(a^2 + b^2)`
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct UserFunction {
+    /// The names of the parameters, in order
+    pub params: Vec<String>,
+    /// The expression evaluated when the function is called, compiled once when it's defined
+    pub body: Ptr<CompiledExpression>,
+}
+
+impl UserFunction {
+    /// Builds a new [`UserFunction`] from its parameters and its body, accepting
+    /// the implicit multiplications ('2x') in the body
+    ///
+    /// # Errors
+    ///
+    /// the first syntax error of the body
+    ///
+    pub fn new(params: &[&str], body: &str) -> Result<UserFunction, YarerError> {
+        Self::new_with(params, body, true)
+    }
+
+    /// Same as [`UserFunction::new`], but the implicit multiplications in the body
+    /// are only accepted if `implicit` is true
+    ///
+    /// # Errors
+    ///
+    /// the first syntax error of the body
+    ///
+    pub fn new_with(params: &[&str], body: &str, implicit: bool) -> Result<UserFunction, YarerError> {
+        let body = CompiledExpression::compile_with(body.trim(), implicit).map_err(|mut errors| errors.remove(0))?;
+        Ok(Self::from_compiled(params, body))
+    }
+
+    /// Builds a new [`UserFunction`] from its parameters and its body, already compiled
+    ///
+    pub(crate) fn from_compiled(params: &[&str], body: CompiledExpression) -> UserFunction {
+        UserFunction {
+            params: params.iter().map(|p| p.to_lowercase()).collect(),
+            body: Ptr::new(body),
+        }
+    }
+
+    /// The number of arguments the function expects
+    ///
+    #[must_use]
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+/// A Rust closure that can be called in an expression, with the values of its arguments
+///
+#[cfg(not(feature = "sync"))]
+pub trait NativeClosure: Fn(&[Number]) -> Result<Number, YarerError> {}
+
+#[cfg(not(feature = "sync"))]
+impl<F: Fn(&[Number]) -> Result<Number, YarerError>> NativeClosure for F {}
+
+/// A Rust closure that can be called in an expression, with the values of its arguments.
+/// With the `sync` feature it must be shareable between threads
+///
+#[cfg(feature = "sync")]
+pub trait NativeClosure: Fn(&[Number]) -> Result<Number, YarerError> + Send + Sync {}
+
+#[cfg(feature = "sync")]
+impl<F: Fn(&[Number]) -> Result<Number, YarerError> + Send + Sync> NativeClosure for F {}
+
+/// A shared pointer to a [`NativeClosure`]
+///
+pub type NativeFn = Ptr<dyn NativeClosure>;
+
+/// A [`Function`] registered in a [`crate::session::Session`]:
+/// either defined by an expression, or implemented in Rust
+///
+#[derive(Clone)]
+pub enum Function {
+    /// A function defined by an expression
+    User(UserFunction),
+    /// A function implemented by a Rust closure
+    Native {
+        /// the number of arguments it accepts
+        arity: Arity,
+        /// the closure
+        fun: NativeFn,
+    },
+}
+
+impl Function {
+    /// The number of arguments accepted by the function
+    ///
+    #[must_use]
+    pub fn arity(&self) -> Arity {
+        match self {
+            Function::User(fun) => Arity::Exact(fun.arity()),
+            Function::Native { arity, .. } => *arity,
+        }
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Function::User(fun) => f.debug_tuple("User").field(fun).finish(),
+            Function::Native { arity, .. } => {
+                f.debug_struct("Native").field("arity", arity).finish_non_exhaustive()
+            }
+        }
+    }
+}
+
+/// The functions defined in a [`crate::session::Session`], by name
+///
+pub(crate) type Functions = Shared<HashMap<String, Function>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_function() {
+        let fun = UserFunction::new(&["A", "b"], " sqrt(a^2 + b^2) ").unwrap();
+        assert_eq!(fun.params, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(fun.body.source(), "sqrt(a^2 + b^2)");
+        assert!(UserFunction::new(&["a"], "2 * (a").is_err());
+        assert!(UserFunction::new_with(&["a"], "2a", false).is_err());
+        assert_eq!(fun.arity(), 2);
+        assert_eq!(Function::User(fun).arity(), Arity::Exact(2));
+    }
+
+    #[test]
+    fn test_arity() {
+        assert!(Arity::Exact(2).accepts(2));
+        assert!(!Arity::Exact(2).accepts(3));
+        assert!(Arity::AtLeast(1).accepts(5));
+        assert!(!Arity::AtLeast(1).accepts(0));
+        assert_eq!(Arity::AtLeast(1).to_string(), "at least 1");
+    }
+}
@@ -1,6 +1,8 @@
 //#![warn(missing_docs)]
 #![warn(clippy::pedantic)]
 #![warn(clippy::cargo)]
+// the duplicated crates are pulled in by the dependencies (clap, rustyline, dirs), not by yarer itself
+#![allow(clippy::multiple_crate_versions)]
 //! Yarer (Yet another (Rusty || Rpn) expression resolver) is a flexible library, written in Rust, for the processing, compilation and evaluation of mathematical expressions using Reverse Polish Notation.
 //!
 //! # Example of usage of the library:
@@ -41,7 +43,7 @@
 //! session.set("x",-1);
 //! println!("The result is {}", resolver.resolve().unwrap());
 //!
-//! session.setf("x",0.001);
+//! session.setf("x",0.001).unwrap();
 //! println!("The result is {}", resolver.resolve().unwrap());
 //! ```
 //!
@@ -51,10 +53,11 @@
 //! # use yarer::{rpn_resolver::RpnResolver, session::Session, token::Number};
 //! # let session: Session = Session::init();
 //! # let mut resolver: RpnResolver = session.process("1/cos(x^2)");
+//! # session.set("x",1);
 //!
 //! let result: Number = resolver.resolve().unwrap();
 //!
-//! let int : i32 = result.clone().into();
+//! let int : i32 = result.clone().try_into().unwrap();
 //! // or
 //! let float : f64 = result.into();
 //! ```
@@ -105,6 +108,9 @@
 //! Sqrt
 //! Max
 //! Min
+//! Sum
+//! Avg
+//! Hypot
 //! Floor
 //! Ceil
 //! Round
@@ -112,11 +118,28 @@
 //! Pdf
 //! Cdf
 //! ```
+/// Abstract syntax tree
+pub mod ast;
+/// `BigMath`
+pub mod bigmath;
+/// Formula cells
+pub mod cell;
+/// `CompiledExpression`
+pub mod compiled;
+/// Error
+pub mod error;
+/// Number formatting
+pub mod format;
+/// User functions
+pub mod function;
 /// Parser
 pub mod parser;
 /// `RpnResolver`
 pub mod rpn_resolver;
 /// Session
 pub mod session;
+/// The state shared between a session and its resolvers: `Rc<RefCell<T>>` by default,
+/// `Arc<RwLock<T>>` with the `sync` feature
+mod shared;
 /// Token
 pub mod token;
@@ -1,8 +1,12 @@
-use crate::token::{self, Operator, Token};
+use crate::{
+    ast::{Callee, Expr},
+    error::{Span, YarerError},
+    token::{self, Bracket, Operator, Token},
+};
 
 use log::debug;
-use once_cell::sync::Lazy;
 use regex::Regex;
+use std::sync::LazyLock;
 
 /// The Parser has 2 primary functions:
 /// to parse the math expression with a Regex and to tokenise the math &[str] expression
@@ -10,36 +14,501 @@ use regex::Regex;
 #[derive(Debug)]
 pub struct Parser;
 
-static EXPRESSION_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"(\d+\.?\d*|\.\d+|[-+*/^(),=×÷!;]|[a-zA-Z_][a-zA-Z0-9_]*|)")
+/// `$n` is the n-th result of a session. Any other non-blank character is captured
+/// by the `unknown` group, to be reported
+///
+static EXPRESSION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        // hex, binary and octal integers, with digits separated by '_' ('0xFF_FF')
+        r"0[xX][0-9a-fA-F]+(?:_[0-9a-fA-F]+)*|0[bB][01]+(?:_[01]+)*|0[oO][0-7]+(?:_[0-7]+)*|",
+        // decimal numbers, with an optional exponent ('1_000', '2.5E+3', '.5e-9')
+        r"(?:\d+(?:_\d+)*(?:\.(?:\d+(?:_\d+)*)?)?|\.\d+(?:_\d+)*)(?:[eE][-+]?\d+(?:_\d+)*)?|",
+        // the comparison and logical operators of two chars
+        r"==|!=|<=|>=|&&|\|\||",
+        // the Unicode operators and constants, and the superscript exponents ('x⁻¹')
+        r"[-+*/^(),=!;\[\]<>×·÷−√≠≤≥]|[a-zA-Z_][a-zA-Z0-9_]*|[πτφ]|(?P<superscript>⁻?[⁰¹²³⁴⁵⁶⁷⁸⁹]+)|",
+        r"\$\d+|(?P<unknown>\S)"
+    ))
         .expect("Should compile regex")
 });
 
+/// The maximum depth of an [`Expr`] tree: the trees are walked recursively, so a deeper one
+/// ('-----...1') is a [`YarerError::NestingLimit`] rather than a stack overflow
+///
+pub const NESTING_LIMIT: usize = 256;
+
+/// A function definition: a name, a list of parameters in brackets, and the body after '='
+///
+static DEFINITION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*([a-zA-Z_][a-zA-Z0-9_]*)\s*\(\s*((?:[a-zA-Z_][a-zA-Z0-9_]*\s*,\s*)*[a-zA-Z_][a-zA-Z0-9_]*)?\s*\)\s*=([^=].*)$",
+    )
+    .expect("Should compile regex")
+});
+
+/// A cell binding: a name and, after ':=', its formula
+///
+static BINDING_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*([a-zA-Z_][a-zA-Z0-9_]*)\s*:=(.*)$").expect("Should compile regex")
+});
+
 impl Parser {
     /// Parses and splits a &str into a vec of &str with
     /// the help of [`EXPRESSION_REGEX`] and then wraps in tokens the &str chunks
     ///
-    pub fn parse(expr: &str) -> Vec<Token> {
-        let vex: Vec<Token<'_>> = EXPRESSION_REGEX
-            .find_iter(expr)
-            .map(|m| m.as_str())
-            .filter_map(|s| Token::tokenize(s))
+    /// # Errors
+    ///
+    /// all the unknown characters and unbalanced brackets found in the expression,
+    /// sorted by position
+    ///
+    pub fn parse(expr: &str) -> Result<Vec<Token<'_>>, Vec<YarerError>> {
+        Ok(Self::parse_spanned(expr)?
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect())
+    }
+
+    /// Same as [`Parser::parse`], but every token comes with its [Span] in the expression
+    ///
+    /// # Errors
+    ///
+    /// all the unknown characters and unbalanced brackets found in the expression,
+    /// sorted by position
+    ///
+    pub fn parse_spanned(expr: &str) -> Result<Vec<(Token<'_>, Span)>, Vec<YarerError>> {
+        Self::parse_spanned_with(expr, true)
+    }
+
+    /// Same as [`Parser::parse_spanned`], but the multiplications left implicit ('2x')
+    /// are only accepted if `implicit` is true, otherwise they are syntax errors
+    ///
+    /// # Errors
+    ///
+    /// all the unknown characters and unbalanced brackets found in the expression,
+    /// sorted by position
+    ///
+    pub fn parse_spanned_with(expr: &str, implicit: bool) -> Result<Vec<(Token<'_>, Span)>, Vec<YarerError>> {
+        Self::parse_spanned_calling(expr, implicit, &|_| true)
+    }
+
+    /// Splits the expression into its tokens, where a name followed by an open bracket is a call
+    /// only if `is_function` accepts it: otherwise, if `implicit` is true, it's a variable
+    /// multiplied by the bracket
+    ///
+    fn parse_spanned_calling<'e>(
+        expr: &'e str,
+        implicit: bool,
+        is_function: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<(Token<'e>, Span)>, Vec<YarerError>> {
+        let mut errors: Vec<YarerError> = Vec::new();
+        let mut vex: Vec<(Token<'_>, Span)> = Vec::new();
+
+        for (token, span) in Self::lex(expr) {
+            if let Some(t) = token {
+                let t = match t {
+                    Token::UserFunction(name, _) if implicit && !is_function(&name.to_lowercase()) => {
+                        Token::Variable(name)
+                    }
+                    t => t,
+                };
+                vex.push((t, span));
+            } else if expr[span.start..].starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                // a number literal too large to be represented ('1e99999999')
+                errors.push(YarerError::Overflow { span });
+            } else {
+                let character = expr[span.start..].chars().next().unwrap_or_default();
+                errors.push(YarerError::UnknownCharacter { character, span });
+            }
+        }
+        errors.extend(Self::check_brackets(&vex));
+
+        if errors.is_empty() {
+            let vex = Self::mod_unary_operators(&vex);
+            Ok(if implicit { Self::insert_implicit_multiplications(vex) } else { vex })
+        } else {
+            errors.sort_by_key(|e| e.span().start);
+            Err(errors)
+        }
+    }
+
+    /// Splits an expression into its tokens with their [Span]s, without checking it: the
+    /// unknown characters, and the number literals too large to be represented, are returned
+    /// as [`None`]. Every variable followed by an open bracket becomes a [`Token::UserFunction`].
+    /// A superscript exponent ('x²') becomes a power, whose operator spans the whole exponent.
+    /// It's meant for incomplete expressions, i.e. to highlight them while they're typed
+    ///
+    #[must_use]
+    pub fn lex(expr: &str) -> Vec<(Option<Token<'_>>, Span)> {
+        let mut vex: Vec<(Option<Token<'_>>, Span)> = Vec::new();
+
+        for caps in EXPRESSION_REGEX.captures_iter(expr) {
+            let Some(m) = caps.get(0) else { continue };
+            let span = Span::new(m.start(), m.end());
+            if caps.name("unknown").is_some() {
+                vex.push((None, span));
+            } else if caps.name("superscript").is_some() {
+                vex.extend(Self::superscript(m.as_str(), span));
+            } else {
+                vex.push((Token::tokenize(m.as_str()), span));
+            }
+        }
+        Self::mark_user_functions(&mut vex);
+        vex
+    }
+
+    /// Parses an expression into its [`Expr`] tree
+    ///
+    /// # Errors
+    ///
+    /// all the unknown characters and unbalanced brackets found in the expression, sorted by
+    /// position, or the first token that can't appear in its position ('1+*2')
+    ///
+    pub fn parse_expr(expr: &str) -> Result<Expr, Vec<YarerError>> {
+        Self::parse_expr_with(expr, true)
+    }
+
+    /// Same as [`Parser::parse_expr`], but the multiplications left implicit ('2x')
+    /// are only accepted if `implicit` is true
+    ///
+    /// # Errors
+    ///
+    /// all the unknown characters and unbalanced brackets found in the expression, sorted by
+    /// position, or the first token that can't appear in its position ('1+*2', or '2x' if
+    /// `implicit` is false)
+    ///
+    pub fn parse_expr_with(expr: &str, implicit: bool) -> Result<Expr, Vec<YarerError>> {
+        Self::parse_expr_calling(expr, implicit, |_| true)
+    }
+
+    /// Same as [`Parser::parse_expr_with`], but a name followed by an open bracket is a call
+    /// only if `is_function` accepts its lowercase name, as the functions of a
+    /// [`crate::session::Session`]: otherwise, if `implicit` is true, it's a variable
+    /// multiplied by the bracket
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::parser::Parser;
+    ///
+    ///      let expr = Parser::parse_expr_calling("x (1 + 2) + f(3)", true, |name| name == "f").unwrap();
+    ///      assert_eq!(expr.to_string(), "x * (1 + 2) + f(3)");
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// the same errors as [`Parser::parse_expr_with`]
+    ///
+    pub fn parse_expr_calling(
+        expr: &str,
+        implicit: bool,
+        is_function: impl Fn(&str) -> bool,
+    ) -> Result<Expr, Vec<YarerError>> {
+        let tokens = Self::parse_spanned_calling(expr, implicit, &is_function)?;
+        Self::build_tree(&tokens).map_err(|e| vec![e])
+    }
+
+    /// The tokens of a superscript exponent: '²' is '^ 2' and '⁻¹' is '^ - 1'
+    ///
+    fn superscript(text: &str, span: Span) -> Vec<(Option<Token<'static>>, Span)> {
+        let mut tokens = vec![(Some(Token::Operator(Operator::Pow)), span)];
+        let mut start = span.start;
+        if let Some(digits) = text.strip_prefix('⁻') {
+            start += text.len() - digits.len();
+            tokens.push((Some(Token::Operator(Operator::Sub)), Span::new(span.start, start)));
+        }
+        let digits: String = text
+            .chars()
+            .filter_map(|c| "⁰¹²³⁴⁵⁶⁷⁸⁹".chars().position(|s| s == c))
+            .filter_map(|d| char::from_digit(u32::try_from(d).ok()?, 10))
             .collect();
+        tokens.push((Token::tokenize(&digits).map(Token::into_owned), Span::new(start, span.end)));
+        tokens
+    }
+
+    /// Splits a function definition such as `hyp(a, b) = sqrt(a^2 + b^2)` into its name,
+    /// its parameters and its body, or returns [`None`] if the expression isn't a definition
+    ///
+    #[must_use]
+    pub fn parse_definition(expr: &str) -> Option<(&str, Vec<&str>, &str)> {
+        let caps = DEFINITION_REGEX.captures(expr)?;
+        let name = caps.get(1)?.as_str();
+        let params = caps
+            .get(2)
+            .map(|p| p.as_str().split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        let body = caps.get(3)?.as_str();
+        Some((name, params, body))
+    }
+
+    /// Splits a cell binding such as `total := price * qty` into its name and its formula,
+    /// or returns [`None`] if the expression isn't a binding
+    ///
+    #[must_use]
+    pub fn parse_binding(expr: &str) -> Option<(&str, &str)> {
+        let caps = BINDING_REGEX.captures(expr)?;
+        Some((caps.get(1)?.as_str(), caps.get(2)?.as_str()))
+    }
+
+    /// Turns every variable followed by an open bracket into a [`Token::UserFunction`]
+    ///
+    fn mark_user_functions(v: &mut [(Option<Token<'_>>, Span)]) {
+        for i in 1..v.len() {
+            if let (Some(Token::Variable(name)), Some(Token::Bracket(Bracket::Open))) = (&v[i - 1].0, &v[i].0) {
+                v[i - 1].0 = Some(Token::UserFunction(name.clone(), 0));
+            }
+        }
+    }
+
+    /// Finds out all the close brackets without an open one, and vice versa
+    ///
+    fn check_brackets(v: &[(Token<'_>, Span)]) -> Vec<YarerError> {
+        let mut errors: Vec<YarerError> = Vec::new();
+        let mut open_brackets: Vec<Span> = Vec::new();
+
+        for (token, span) in v {
+            match token {
+                Token::Bracket(Bracket::Open) => open_brackets.push(*span),
+                Token::Bracket(Bracket::Close) if open_brackets.pop().is_none() => {
+                    errors.push(YarerError::UnbalancedBracket { span: *span });
+                }
+                _ => (),
+            }
+        }
+        errors.extend(
+            open_brackets
+                .into_iter()
+                .map(|span| YarerError::UnbalancedBracket { span }),
+        );
+        errors
+    }
+
+    /// Builds the [`Expr`] tree of an infix expression with the Shunting Yard algorithm:
+    /// instead of writing the operators in postfix order, each one is applied
+    /// to the operands already built
+    ///
+    fn build_tree(infix: &[(Token<'_>, Span)]) -> Result<Expr, YarerError> {
+        /*  Create an empty stack for keeping operators, and one for the operands built so far
+        with their depth. */
+        let mut operators_stack: Vec<(Token, Span)> = Vec::new();
+        let mut operands_stack: Vec<(Expr, usize)> = Vec::new();
+        let mut statements: Vec<Expr> = Vec::new();
+        /* The number of arguments between each open bracket and its close one. */
+        let mut arg_counts: Vec<usize> = Vec::new();
+        let mut previous: Option<&Token> = None;
+
+        /* Scan the infix expression from left to right. */
+        for (t, span) in infix {
+            let span = *span;
+            match t {
+                /* If the token is an operand or a variable, it's a leaf of the tree. */
+                Token::Operand(_) | Token::Variable(_) => Self::apply(t, span, &mut operands_stack)?,
+
+                /* If the token is a left parenthesis, push it on the stack. */
+                Token::Bracket(Bracket::Open) => {
+                    operators_stack.push((t.clone(), span));
+                    arg_counts.push(1);
+                }
+
+                /* If the token is a right parenthesis, apply the operators until the left one,
+                and then the function called with the arguments between the brackets, if any. */
+                Token::Bracket(Bracket::Close) => {
+                    let args = match previous {
+                        Some(Token::Bracket(Bracket::Open)) => 0,
+                        _ => arg_counts.last().copied().unwrap_or(1),
+                    };
+                    arg_counts.pop();
+                    while let Some((token, op_span)) = operators_stack.pop() {
+                        if token == Token::Bracket(Bracket::Open) {
+                            // the function is called with the arguments counted so far
+                            let call = match operators_stack.last() {
+                                Some((Token::Function(fun, _), fun_span)) => {
+                                    Some((Token::Function(*fun, args), *fun_span))
+                                }
+                                Some((Token::UserFunction(name, _), fun_span)) => {
+                                    Some((Token::UserFunction(name.clone(), args), *fun_span))
+                                }
+                                _ => None,
+                            };
+                            if let Some((call, fun_span)) = call {
+                                operators_stack.pop();
+                                Self::apply(&call, fun_span, &mut operands_stack)?;
+                            }
+                            break;
+                        }
+                        Self::apply(&token, op_span, &mut operands_stack)?;
+                    }
+                }
+
+                Token::Comma => {
+                    if let Some(count) = arg_counts.last_mut() {
+                        *count += 1;
+                    }
+                    while let Some((token, op_span)) = operators_stack.pop() {
+                        if token == Token::Bracket(Bracket::Open) {
+                            operators_stack.push((token, op_span));
+                            break;
+                        }
+                        Self::apply(&token, op_span, &mut operands_stack)?;
+                    }
+                }
+
+                /* A semicolon ends a statement: apply all the operators left. */
+                Token::SemiColon => {
+                    Self::apply_all(&mut operators_stack, &mut operands_stack)?;
+                    statements.extend(Self::statement(&mut operands_stack)?);
+                }
+
+                /* The functions and the prefix operators have no left operand,
+                so they can't apply the operators before them. */
+                Token::Operator(Operator::Une | Operator::Not) | Token::Function(..) | Token::UserFunction(..) => {
+                    operators_stack.push((t.clone(), span));
+                }
+
+                Token::Operator(_) => {
+                    while let Some((op2, op_span)) = operators_stack.pop() {
+                        let apply = match op2 {
+                            Token::Operator(_) => Token::compare_operator_priority(t, &op2),
+                            Token::Function(..) | Token::UserFunction(..) => true,
+                            _ => false,
+                        };
+                        if !apply {
+                            operators_stack.push((op2, op_span));
+                            break;
+                        }
+                        Self::apply(&op2, op_span, &mut operands_stack)?;
+                    }
+                    operators_stack.push((t.clone(), span));
+                }
+            }
+            previous = Some(t);
+            debug!(
+                "Inspecting... {} - OUT {} - OP - {}",
+                t,
+                operands_stack.len(),
+                operators_stack.len()
+            );
+        }
 
-        Self::mod_unary_operators(&vex)
+        /* After all tokens are read, apply the operators left on the stack. */
+        Self::apply_all(&mut operators_stack, &mut operands_stack)?;
+        statements.extend(Self::statement(&mut operands_stack)?);
+
+        match statements.len() {
+            0 => {
+                let start = infix.first().map(|(_, s)| s.start).unwrap_or_default();
+                let end = infix.last().map(|(_, s)| s.end).unwrap_or_default();
+                Err(YarerError::Malformed { span: Span::new(start, end) })
+            }
+            1 => Ok(statements.remove(0)),
+            _ => Ok(Expr::Sequence(statements)),
+        }
+    }
+
+    /// Applies all the operators left on the stack, until the end of a statement
+    ///
+    fn apply_all(
+        operators_stack: &mut Vec<(Token<'_>, Span)>,
+        operands_stack: &mut Vec<(Expr, usize)>,
+    ) -> Result<(), YarerError> {
+        while let Some((token, span)) = operators_stack.pop() {
+            if token == Token::Bracket(Bracket::Open) {
+                return Err(YarerError::UnbalancedBracket { span });
+            }
+            Self::apply(&token, span, operands_stack)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the tree of a statement, or [`None`] if it's empty ('1;;2').
+    /// Two operands without an operator between them are an error ('1 2')
+    ///
+    fn statement(operands_stack: &mut Vec<(Expr, usize)>) -> Result<Option<Expr>, YarerError> {
+        if let Some((unexpected, _)) = operands_stack.get(1) {
+            return Err(YarerError::UnexpectedToken {
+                token: unexpected.to_string(),
+                span: unexpected.span(),
+            });
+        }
+        Ok(operands_stack.pop().map(|(expr, _)| expr))
+    }
+
+    /// Applies an operator or a function to the operands on top of the stack,
+    /// and pushes the resulting node, one level deeper than its deepest child.
+    /// An operand or a variable is pushed as a leaf
+    ///
+    fn apply(token: &Token<'_>, span: Span, operands_stack: &mut Vec<(Expr, usize)>) -> Result<(), YarerError> {
+        let unexpected = || YarerError::UnexpectedToken {
+            token: token.symbol(),
+            span,
+        };
+        let (node, depth) = match token {
+            Token::Operand(value) => (Expr::Number { value: value.clone(), span }, 0),
+            Token::Variable(name) => (Expr::Variable { name: name.to_string(), span }, 0),
+            Token::Operator(op) if op.is_unary() => {
+                let (operand, depth) = operands_stack.pop().ok_or_else(unexpected)?;
+                let node = Expr::Unary {
+                    op: *op,
+                    operand: Box::new(operand),
+                    span,
+                };
+                (node, depth)
+            }
+            Token::Operator(op) => {
+                let (right, right_depth) = operands_stack.pop().ok_or_else(unexpected)?;
+                let (left, left_depth) = operands_stack.pop().ok_or_else(unexpected)?;
+                let node = match (op, left) {
+                    (Operator::Eql, Expr::Variable { name, span: target }) => Expr::Assign {
+                        name,
+                        target,
+                        value: Box::new(right),
+                        span,
+                    },
+                    (Operator::Eql, _) => return Err(YarerError::InvalidAssignment { span }),
+                    (op, left) => Expr::Binary {
+                        op: *op,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        span,
+                    },
+                };
+                (node, left_depth.max(right_depth))
+            }
+            Token::Function(_, found) | Token::UserFunction(_, found) => {
+                if operands_stack.len() < *found {
+                    return Err(unexpected());
+                }
+                let function = match token {
+                    Token::Function(fun, _) => Callee::Math(*fun),
+                    _ => Callee::User(token.symbol()),
+                };
+                let (args, depths): (Vec<Expr>, Vec<usize>) =
+                    operands_stack.split_off(operands_stack.len() - found).into_iter().unzip();
+                let node = Expr::Call { function, args, span };
+                (node, depths.into_iter().max().unwrap_or_default())
+            }
+            _ => return Err(unexpected()),
+        };
+        if depth >= NESTING_LIMIT {
+            return Err(YarerError::NestingLimit { span });
+        }
+        operands_stack.push((node, depth + 1));
+        Ok(())
     }
 
     /// Finds out all the unary operators that are present in the expression
     ///
-    fn mod_unary_operators<'a>(v: &[Token<'a>]) -> Vec<Token<'a>> {
-        let mut mod_vec: Vec<Token> = Vec::new();
+    fn mod_unary_operators<'a>(v: &[(Token<'a>, Span)]) -> Vec<(Token<'a>, Span)> {
+        let mut mod_vec: Vec<(Token, Span)> = Vec::new();
         let mut expect_operand_next = true;
 
-        for token in v {
-            debug!("{}", token);
+        for (token, span) in v {
+            debug!("{token}");
 
             match &token {
-                Token::Operand(_) | Token::Variable(_) | Token::Operator(Operator::Fac) => {
+                Token::Operand(_)
+                | Token::Variable(_)
+                | Token::Operator(Operator::Fac)
+                | Token::Bracket(Bracket::Close) => {
                     expect_operand_next = false;
                 }
                 Token::Operator(o) => {
@@ -52,7 +521,7 @@ impl Parser {
                             }
                             token::Operator::Sub => {
                                 // an unary - is a special right-associative op with the highest precedence
-                                mod_vec.push(token::Token::Operator(token::Operator::Une));
+                                mod_vec.push((token::Token::Operator(token::Operator::Une), *span));
                                 continue;
                             }
                             _ => (),
@@ -60,12 +529,41 @@ impl Parser {
                     }
                     expect_operand_next = true;
                 }
-                Token::Comma | Token::SemiColon => {
+                Token::Comma | Token::SemiColon | Token::Bracket(Bracket::Open) => {
                     expect_operand_next = true;
                 }
                 _ => (),
             }
-            mod_vec.push(token.clone());
+            mod_vec.push((token.clone(), *span));
+        }
+        mod_vec
+    }
+
+    /// Inserts the multiplications left implicit, as in '2x', '3(4+5)', '(a+b)(a-b)' or '2pi':
+    /// a number, a variable, a closing bracket or a '!' followed by a variable, a function
+    /// or an open bracket. A number can only follow a closing bracket or a '!', so '1 2' is
+    /// still an error, and a function call ('f(2)') isn't a multiplication.
+    /// The inserted '*' has the usual precedence ('1/2x' is '(1/2)*x') and an empty span,
+    /// at the start of the second factor
+    ///
+    fn insert_implicit_multiplications(v: Vec<(Token<'_>, Span)>) -> Vec<(Token<'_>, Span)> {
+        let mut mod_vec: Vec<(Token, Span)> = Vec::with_capacity(v.len());
+        for (token, span) in v {
+            let factor = matches!(
+                token,
+                Token::Variable(_) | Token::Function(..) | Token::UserFunction(..) | Token::Bracket(Bracket::Open)
+            );
+            let implicit = match mod_vec.last() {
+                Some((Token::Operand(_) | Token::Variable(_), _)) => factor,
+                Some((Token::Operator(Operator::Fac) | Token::Bracket(Bracket::Close), _)) => {
+                    factor || matches!(token, Token::Operand(_))
+                }
+                _ => false,
+            };
+            if implicit {
+                mod_vec.push((Token::Operator(Operator::Mul), Span::new(span.start, span.start)));
+            }
+            mod_vec.push((token, span));
         }
         mod_vec
     }
@@ -81,7 +579,7 @@ mod tests {
     fn test_parse_valid() {
         assert_eq!(
             Parser::parse("1+2*3/(4-5)"),
-            (vec![
+            Ok(vec![
                 Token::Operand(Number::NaturalNumber(BigInt::from(1u8))),
                 Token::Operator(Operator::Add),
                 Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
@@ -97,6 +595,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_spanned() {
+        let spans: Vec<Span> = Parser::parse_spanned("12 + sin(x)")
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 2),
+                Span::new(3, 4),
+                Span::new(5, 8),
+                Span::new(8, 9),
+                Span::new(9, 10),
+                Span::new(10, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Parser::parse(") 2 $ (3 @ 4"),
+            Err(vec![
+                YarerError::UnbalancedBracket { span: Span::new(0, 1) },
+                YarerError::UnknownCharacter { character: '$', span: Span::new(4, 5) },
+                YarerError::UnbalancedBracket { span: Span::new(6, 7) },
+                YarerError::UnknownCharacter { character: '@', span: Span::new(9, 10) },
+            ])
+        );
+        assert!(Parser::parse("[(1 + 2) * 3]").is_ok());
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let natural = |n: i64| Token::Operand(Number::NaturalNumber(BigInt::from(n)));
+        assert_eq!(
+            Parser::parse("0xFF + 0b1010 - 0o17 * 1_000_000"),
+            Ok(vec![
+                natural(255),
+                Token::Operator(Operator::Add),
+                natural(10),
+                Token::Operator(Operator::Sub),
+                natural(15),
+                Token::Operator(Operator::Mul),
+                natural(1_000_000),
+            ])
+        );
+        // an exponent needs its digits, otherwise 'e' is the constant
+        let tokens = Parser::parse_spanned_with("6.022e23 2e-x 1E+3", false).unwrap();
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens[1].0, natural(2));
+        assert_eq!(tokens[2].0, Token::Variable("e".into()));
+        // a separator must be between two digits
+        assert_eq!(Parser::lex("1__0")[1].0, Some(Token::Variable("__0".into())));
+        assert_eq!(Parser::parse("1 + 1e99999999"), Err(vec![YarerError::Overflow { span: Span::new(4, 14) }]));
+    }
+
+    #[test]
+    fn test_unicode() {
+        assert_eq!(Parser::parse("2×3·4÷5−1"), Parser::parse("2*3*4/5-1"));
+        assert_eq!(Parser::parse("√π + τ/φ"), Parser::parse("sqrt pi + tau/phi"));
+        assert_eq!(Parser::parse("x² + y⁻¹⁰"), Parser::parse("x^2 + y^-10"));
+        assert_eq!(Parser::parse_expr("-x² + 2³!").unwrap().to_string(), "-x^2 + 2^3!");
+
+        let spans: Vec<Span> = Parser::lex("y⁻¹⁰").into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, [Span::new(0, 1), Span::new(1, 9), Span::new(1, 4), Span::new(4, 9)]);
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        assert_eq!(Parser::parse("2x + 3(4)"), Parser::parse("2*x + 3*(4)"));
+        assert_eq!(Parser::parse("(a+b)(a-b)"), Parser::parse("(a+b)*(a-b)"));
+        assert_eq!(Parser::parse("2pi sqrt(2) 3! 4"), Parser::parse("2*pi*sqrt(2)*3!*4"));
+        assert_eq!(Parser::parse("f(x)g(y)"), Parser::parse("f(x)*g(y)"));
+        assert_eq!(Parser::parse_expr("1/2x").unwrap().to_string(), "1 / 2 * x");
+
+        let spans: Vec<Span> = Parser::parse_spanned("2x").unwrap().into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, [Span::new(0, 1), Span::new(1, 1), Span::new(1, 2)]);
+        assert_eq!(
+            Parser::parse_expr_with("2x", false),
+            Err(vec![YarerError::UnexpectedToken { token: "x".to_string(), span: Span::new(1, 2) }])
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let rpn = |expr| -> String {
+            let rpn = Parser::parse_expr(expr).unwrap().to_rpn();
+            rpn.iter().map(|(t, _)| t.symbol()).collect::<Vec<String>>().join(" ")
+        };
+        assert_eq!(rpn("age >= 18 && income > 3 * rent"), "age 18 >= income 3 rent * > &&");
+        assert_eq!(rpn("a || b && not c == d"), "a b c d == not && ||");
+        assert_eq!(rpn("x = 1 + 2 != 3"), "x 1 2 + 3 != =");
+        assert_eq!(rpn("3!=6"), "3 6 !=");
+        assert_eq!(Parser::parse_expr("1 + not 0").unwrap().to_string(), "1 + (not 0)");
+        assert_eq!(Parser::parse_expr("not (a && b)").unwrap().to_string(), "not (a && b)");
+    }
+
+    #[test]
+    fn test_lex() {
+        let tokens: Vec<Option<Token>> = Parser::lex("f(2 $ (x").into_iter().map(|(t, _)| t).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Some(Token::UserFunction("f".into(), 0)),
+                Some(Token::Bracket(Bracket::Open)),
+                Some(Token::Operand(Number::NaturalNumber(2.into()))),
+                None,
+                Some(Token::Bracket(Bracket::Open)),
+                Some(Token::Variable("x".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_definition() {
+        assert_eq!(
+            Parser::parse_definition("hyp(a, b) = sqrt(a^2 + b^2)"),
+            Some(("hyp", vec!["a", "b"], " sqrt(a^2 + b^2)"))
+        );
+        assert_eq!(Parser::parse_definition("answer() = 42"), Some(("answer", vec![], " 42")));
+        assert_eq!(Parser::parse_definition("f(2) = 3"), None);
+        assert_eq!(Parser::parse_definition("x = 3"), None);
+        assert_eq!(Parser::parse_binding("total := price * qty"), Some(("total", " price * qty")));
+        assert_eq!(Parser::parse_binding("total = price * qty"), None);
+        assert_eq!(
+            Parser::parse("f(x)"),
+            Ok(vec![
+                Token::UserFunction("f".into(), 0),
+                Token::Bracket(Bracket::Open),
+                Token::Variable("x".into()),
+                Token::Bracket(Bracket::Close),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        assert_eq!(
+            Parser::parse_expr("-x!"),
+            Ok(Expr::Unary {
+                op: Operator::Une,
+                operand: Box::new(Expr::Unary {
+                    op: Operator::Fac,
+                    operand: Box::new(Expr::Variable {
+                        name: "x".to_string(),
+                        span: Span::new(1, 2)
+                    }),
+                    span: Span::new(2, 3)
+                }),
+                span: Span::new(0, 1)
+            })
+        );
+        assert!(matches!(
+            Parser::parse_expr("x = 1; hyp(x, 2);"),
+            Ok(Expr::Sequence(exprs)) if exprs.len() == 2
+        ));
+        assert_eq!(
+            Parser::parse_expr("(1 +) * 2"),
+            Err(vec![YarerError::UnexpectedToken {
+                token: "+".to_string(),
+                span: Span::new(3, 4)
+            }])
+        );
+        assert_eq!(
+            Parser::parse_expr("1 2"),
+            Err(vec![YarerError::UnexpectedToken {
+                token: "2".to_string(),
+                span: Span::new(2, 3)
+            }])
+        );
+        assert_eq!(
+            Parser::parse_expr("x + 1 = 2"),
+            Err(vec![YarerError::InvalidAssignment { span: Span::new(6, 7) }])
+        );
+        assert_eq!(
+            Parser::parse_expr("()"),
+            Err(vec![YarerError::Malformed { span: Span::new(0, 2) }])
+        );
+    }
+
     #[test]
     fn test_multiple_unary_ops2() {
         // -(+(-5*-5)) to #((#5*#5))
@@ -128,7 +809,11 @@ mod tests {
             Token::Bracket(Bracket::Close),
         ];
 
-        let result = Parser::mod_unary_operators(&input);
+        let spanned: Vec<(Token, Span)> = input.into_iter().map(|t| (t, Span::default())).collect();
+        let result: Vec<Token> = Parser::mod_unary_operators(&spanned)
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
         assert_eq!(result, expected);
     }
 }
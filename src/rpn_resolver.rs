@@ -1,26 +1,37 @@
 use crate::{
+    bigmath::BigMath,
+    cell::{self, Cells},
+    error::{Span, YarerError},
+    function::{Arity, Function, Functions, UserFunction, CALL_DEPTH_LIMIT},
     parser::Parser,
+    session::{Settings, ANS},
+    shared::{read, shared, write, Shared},
     token::{self, MathFunction, Number, Operator, Token},
 };
 use statrs::distribution::{Continuous, ContinuousCDF, Normal};
-use anyhow::anyhow;
 use log::debug;
 use std::{
-    cell::RefCell,
-    collections::{HashMap, VecDeque},
-    fmt::Display,
-    rc::Rc,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, VecDeque},
 };
 
+use num::complex::{Complex, Complex64};
 use num::{BigInt, BigUint, One, Zero};
 use num_rational::BigRational;
-use num_traits::ToPrimitive;
+use num_traits::{Signed, ToPrimitive};
 
-static MALFORMED_ERR: &str = "Runtime Error: The mathematical expression is malformed.";
-static DIVISION_ZERO_ERR: &str = "Runtime error: Divide by zero.";
-static NO_VARIABLE_ERR: &str = "Runtime error: No variable has been defined for assignment.";
-static FACTORIAL_NATURAL_ERR: &str =
-    "Runtime error: Factorial is only defined for non-negative integers.";
+/// The largest integer whose factorial is computed, before an overflow error
+///
+const FACTORIAL_LIMIT: u64 = 20_000;
+
+/// The heap of variables shared between a [`crate::session::Session`] and its resolvers
+///
+pub(crate) type Heap = Shared<HashMap<String, Number>>;
+
+/// The results of the expressions resolved by a [`crate::session::Session`], read as `$1`, `$2`, ...
+///
+pub(crate) type Results = Shared<Vec<Number>>;
 
 /// The main [`RpnResolver`] contains the core logic of Yarer
 /// for parsing and evaluating a math expression.
@@ -29,37 +40,166 @@ static FACTORIAL_NATURAL_ERR: &str =
 /// a heap of local variables borrowed from a [`Session`]
 ///
 pub struct RpnResolver<'a> {
-    rpn_expr: VecDeque<Token<'a>>,
-    local_heap: Rc<RefCell<HashMap<String, Number>>>,
+    rpn_expr: Cow<'a, [(Token<'a>, Span)]>,
+    parse_errors: Vec<YarerError>,
+    local_heap: Heap,
+    // the parameters and the variables assigned in the body of a user function, if it's one
+    frame: Option<Heap>,
+    settings: Shared<Settings>,
+    functions: Functions,
+    cells: Cells,
+    results: Results,
+    record: bool,
+    depth: usize,
 }
 
 impl RpnResolver<'_> {
     /// Generates a new [`RpnResolver`] instance with borrowed heap
     ///
-    pub fn parse_with_borrowed_heap<'a>(
-        exp: &'a str,
-        borrowed_heap: Rc<RefCell<HashMap<String, Number>>>,
-    ) -> RpnResolver<'a> {
-        let tokenised_expr: Vec<Token<'a>> = Parser::parse(exp);
-        let (rpn_expr, local_heap) =
-            RpnResolver::reverse_polish_notation(&tokenised_expr, borrowed_heap);
+    pub fn parse_with_borrowed_heap(
+        exp: &str,
+        borrowed_heap: Heap,
+    ) -> RpnResolver<'_> {
+        RpnResolver::parse_with_borrowed_session(
+            exp,
+            borrowed_heap,
+            shared(Settings::default()),
+            shared(HashMap::new()),
+            shared(BTreeMap::new()),
+            shared(Vec::new()),
+        )
+    }
+
+    /// Generates a new [`RpnResolver`] instance with the borrowed heap, settings, functions, cells
+    /// and results of a [`crate::session::Session`]
+    ///
+    pub(crate) fn parse_with_borrowed_session(
+        exp: &str,
+        borrowed_heap: Heap,
+        settings: Shared<Settings>,
+        functions: Functions,
+        cells: Cells,
+        results: Results,
+    ) -> RpnResolver<'_> {
+        let implicit = read(&settings).implicit;
+        let is_function = |name: &str| read(&functions).contains_key(name);
+        let (rpn_expr, parse_errors) = match Parser::parse_expr_calling(exp, implicit, is_function) {
+            Ok(expr) => (expr.to_rpn(), Vec::new()),
+            Err(errors) => (Vec::new(), errors),
+        };
 
         RpnResolver {
-            rpn_expr,
-            local_heap,
+            rpn_expr: Cow::Owned(rpn_expr),
+            parse_errors,
+            local_heap: borrowed_heap,
+            frame: None,
+            settings,
+            functions,
+            cells,
+            results,
+            record: false,
+            depth: 0,
         }
     }
 
-    /// This method evaluates the rpn expression stack
+    /// Generates a new [`RpnResolver`] instance for an expression already converted to RPN,
+    /// with the borrowed heap, settings, functions, cells and results of a [`crate::session::Session`]
+    ///
+    pub(crate) fn with_borrowed_rpn<'a>(
+        rpn_expr: &'a [(Token<'a>, Span)],
+        borrowed_heap: Heap,
+        settings: Shared<Settings>,
+        functions: Functions,
+        cells: Cells,
+        results: Results,
+    ) -> RpnResolver<'a> {
+        RpnResolver {
+            rpn_expr: Cow::Borrowed(rpn_expr),
+            parse_errors: Vec::new(),
+            local_heap: borrowed_heap,
+            frame: None,
+            settings,
+            functions,
+            cells,
+            results,
+            record: false,
+            depth: 0,
+        }
+    }
+
+    /// Makes [`RpnResolver::resolve`] store its result in `ans` and append it to the results
+    ///
+    pub(crate) fn recording(mut self) -> Self {
+        self.record = true;
+        self
+    }
+
+    /// Returns all the syntax errors found by the [`Parser`] in the expression, sorted by position.
+    /// An expression with syntax errors can't be resolved
+    ///
+    #[must_use]
+    pub fn parse_errors(&self) -> &[YarerError] {
+        &self.parse_errors
+    }
+
+    /// This method evaluates the rpn expression stack. If the resolver has been built by
+    /// [`crate::session::Session::process_recorded`], the result is also stored in the `ans` variable
+    /// and appended to the results of the session, to be read as `$1`, `$2`, ...
+    ///
+    /// # Errors
     ///
-    pub fn resolve(&mut self) -> anyhow::Result<Number> {
+    /// the first of the [`RpnResolver::parse_errors`], or a [`YarerError`] if the expression
+    /// is malformed or can't be evaluated (i.e. a division by zero)
+    ///
+    pub fn resolve(&mut self) -> Result<Number, YarerError> {
+        // a single write guard for the whole evaluation, so that 'a = a + 1' is atomic
+        let heap = Shared::clone(&self.local_heap);
+        let mut heap = write(&heap);
+        self.resolve_in(&mut heap)
+    }
+
+    /// Same as [`RpnResolver::resolve`], with the heap already locked by the caller
+    ///
+    pub(crate) fn resolve_in(&mut self, heap: &mut HashMap<String, Number>) -> Result<Number, YarerError> {
+        let value = self.evaluate(heap)?;
+        if self.record {
+            write(&self.results).push(value.clone());
+            self.assign(heap, ANS, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// A new [`RpnResolver`] sharing the heap, settings, functions, cells and results of this one
+    ///
+    fn nested<'b>(&self, rpn_expr: &'b [(Token<'b>, Span)]) -> RpnResolver<'b> {
+        RpnResolver::with_borrowed_rpn(
+            rpn_expr,
+            Shared::clone(&self.local_heap),
+            Shared::clone(&self.settings),
+            Shared::clone(&self.functions),
+            Shared::clone(&self.cells),
+            Shared::clone(&self.results),
+        )
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn evaluate(&mut self, heap: &mut HashMap<String, Number>) -> Result<Number, YarerError> {
+        if let Some(err) = self.parse_errors.first() {
+            return Err(err.clone());
+        }
         let zero: Number = Number::NaturalNumber(Zero::zero());
-        let minus_one: Number = Number::NaturalNumber(BigInt::from(-1));
+
+        let precision = read(&self.settings).precision;
+        let strict = read(&self.settings).strict;
+        let complex = read(&self.settings).complex;
 
         let mut result_stack: VecDeque<Number> = VecDeque::new();
-        let mut var_stack: VecDeque<Option<String>> = VecDeque::new();
+        // the variable (and its span) each value has been read from, if any
+        let mut var_stack: VecDeque<Option<(String, Span)>> = VecDeque::new();
+        let targets = Self::assignment_targets(&self.rpn_expr);
 
-        for t in &self.rpn_expr {
+        for (i, (t, span)) in self.rpn_expr.iter().enumerate() {
+            let span = *span;
             match t {
                 Token::Operand(n) => {
                     result_stack.push_back(n.clone());
@@ -68,335 +208,618 @@ impl RpnResolver<'_> {
                 Token::Operator(op) => {
                     let right_value: Number = result_stack
                         .pop_back()
-                        .ok_or_else(|| anyhow!("{} {}", MALFORMED_ERR, "Invalid Right Operand."))?;
+                        .ok_or_else(|| Self::unexpected(t, span))?;
 
                     var_stack.pop_back();
 
-                    let mut left_value = if op != &Operator::Une && op != &Operator::Fac {
-                        result_stack.pop_back().ok_or_else(|| {
-                            anyhow!("{} {}", MALFORMED_ERR, "Invalid Left Operand.")
-                        })?
-                    } else {
+                    let left_value = if op.is_unary() {
                         zero.clone()
-                    };
-                    let left_var = if op != &Operator::Une && op != &Operator::Fac {
-                        var_stack.pop_back().unwrap_or(None)
                     } else {
+                        result_stack
+                            .pop_back()
+                            .ok_or_else(|| Self::unexpected(t, span))?
+                    };
+                    let left_var = if op.is_unary() {
                         None
+                    } else {
+                        var_stack.pop_back().flatten()
                     };
 
-                    match op {
-                        Operator::Add => {
-                            result_stack.push_back(left_value + right_value);
-                            var_stack.push_back(None);
-                        }
-                        Operator::Sub => {
-                            result_stack.push_back(left_value - right_value);
-                            var_stack.push_back(None);
-                        }
-                        Operator::Mul => {
-                            result_stack.push_back(left_value * right_value);
-                            var_stack.push_back(None);
-                        }
-                        Operator::Div => {
-                            if right_value == zero {
-                                return Err(anyhow!(DIVISION_ZERO_ERR));
-                            }
-                            left_value = Number::DecimalNumber(
-                                BigRational::from_float(f64::from(left_value))
-                                    .expect("valid float"),
-                            );
-                            result_stack.push_back(left_value / right_value);
-                            var_stack.push_back(None);
-                        }
-                        Operator::Pow => {
-                            if right_value < zero {
-                                if left_value == zero {
-                                    return Err(anyhow!(DIVISION_ZERO_ERR));
-                                }
-                                left_value = Number::DecimalNumber(
-                                    BigRational::from_float(f64::from(left_value))
-                                        .expect("valid float"),
-                                );
-                            }
-                            result_stack.push_back(left_value ^ right_value);
-                            var_stack.push_back(None);
-                        }
-                        Operator::Eql => {
-                            if let Some(var) = left_var {
-                                self.local_heap
-                                    .borrow_mut()
-                                    .insert(var.clone(), right_value.clone());
-
-                                result_stack.push_back(right_value);
-                                var_stack.push_back(None);
-                            } else {
-                                return Err(anyhow!(NO_VARIABLE_ERR));
-                            }
-                        }
-                        Operator::Fac => {
-                            // factorial. Only for non-negative integers
-                            match right_value {
-                                Number::NaturalNumber(v) => {
-                                    if v < Zero::zero() {
-                                        return Err(anyhow!(FACTORIAL_NATURAL_ERR));
-                                    }
-                                    let n = v.to_u64().ok_or_else(|| {
-                                        anyhow!("Runtime Error: Factorial operand is too large")
-                                    })?;
-                                    let res = Self::factorial_helper(n.into());
-                                result_stack.push_back(Number::NaturalNumber(res.into()));
-                                var_stack.push_back(None);
-                            }
-                            Number::DecimalNumber(_) => {
-                                return Err(anyhow!(FACTORIAL_NATURAL_ERR));
-                            }
-                        }
-                        }
-                        Operator::Une => {
-                            //# unary neg
-                            result_stack.push_back(right_value * minus_one.clone());
-                            var_stack.push_back(None);
-                        }
-                    }
+                    let res = self.apply_operator(heap, *op, (left_value, right_value), left_var, span)?;
+                    result_stack.push_back(res);
+                    var_stack.push_back(None);
                 }
                 Token::Variable(v) => {
                     let var_name = v.to_lowercase();
-                    debug!("Heap {:?}", self.local_heap);
-                    let heap = self.local_heap.borrow();
-                    let n = heap
-                        .get(&var_name)
-                        .cloned()
-                        .unwrap_or_else(|| Number::DecimalNumber(BigRational::from_integer(BigInt::zero())));
+                    debug!("Heap {heap:?}");
+                    // an undefined variable is zero, unless it's read in strict mode.
+                    // The left side of an assignment is the only place for an undefined variable
+                    let n = match self.lookup(heap, &var_name) {
+                        Some(n) => n,
+                        None if strict && !targets[i] => {
+                            return Err(YarerError::UnknownVariable { name: var_name, span });
+                        }
+                        None => zero.clone(),
+                    };
                     result_stack.push_back(n);
-                    var_stack.push_back(Some(var_name));
+                    var_stack.push_back(Some((var_name, span)));
                 }
-                Token::Function(fun) => {
-                    let value: Number = result_stack.pop_back().ok_or(anyhow!(
-                        "{} {}",
-                        MALFORMED_ERR,
-                        "Wrong use of function"
-                    ))?;
-                    var_stack.pop_back();
+                Token::Function(fun, found) => {
+                    let (fun, found) = (*fun, *found);
+                    let expected = fun.arity();
+                    if !expected.accepts(found) {
+                        return Err(YarerError::ArityMismatch {
+                            function: t.symbol(),
+                            expected,
+                            found,
+                            span,
+                        });
+                    }
+                    if result_stack.len() < found {
+                        return Err(Self::unexpected(t, span));
+                    }
+                    let args: Vec<Number> = result_stack.split_off(result_stack.len() - found).into();
+                    var_stack.truncate(var_stack.len() - found);
+
+                    if let Arity::AtLeast(_) = expected {
+                        let res = Self::variadic_function(fun, args, precision, span)?;
+                        result_stack.push_back(res);
+                        var_stack.push_back(None);
+                        continue;
+                    }
+                    let value = args.into_iter().next().ok_or_else(|| Self::unexpected(t, span))?;
+
+                    let res = Self::function(fun, value, precision, complex, span)?;
+                    result_stack.push_back(res);
+                    var_stack.push_back(None);
+                }
+                Token::UserFunction(name, found) => {
+                    let (name, found) = (name.to_lowercase(), *found);
+                    let fun = read(&self.functions)
+                        .get(&name)
+                        .cloned()
+                        .ok_or_else(|| YarerError::UnknownFunction {
+                            name: name.clone(),
+                            span,
+                        })?;
+
+                    let expected = fun.arity();
+                    if !expected.accepts(found) {
+                        return Err(YarerError::ArityMismatch {
+                            function: name,
+                            expected,
+                            found,
+                            span,
+                        });
+                    }
+                    if result_stack.len() < found {
+                        return Err(Self::unexpected(t, span));
+                    }
+                    let args: Vec<Number> = result_stack.split_off(result_stack.len() - found).into();
+                    var_stack.truncate(var_stack.len() - found);
 
                     let res = match fun {
-                        MathFunction::Sin => f64::sin(value.into()),
-                        MathFunction::Cos => f64::cos(value.into()),
-                        MathFunction::Tan => f64::tan(value.into()),
-                        MathFunction::ASin => f64::asin(value.into()),
-                        MathFunction::ACos => f64::acos(value.into()),
-                        MathFunction::ATan => f64::atan(value.into()),
-                        MathFunction::Ln => f64::ln(value.into()),
-                        MathFunction::Log => f64::log10(value.into()),
-                        MathFunction::Abs => f64::abs(value.into()),
-                        MathFunction::Max => {
-                            let value2: Number = result_stack.pop_back().ok_or(anyhow!(
-                                "{} {}",
-                                MALFORMED_ERR,
-                                "Wrong number of parameters for function Max"
-                            ))?;
-                            var_stack.pop_back();
-                            f64::max(value.into(), value2.into())
-                        }
-                        MathFunction::Min => {
-                            let value2: Number = result_stack.pop_back().ok_or(anyhow!(
-                                "{} {}",
-                                MALFORMED_ERR,
-                                "Wrong number of parameters for function Min"
-                            ))?;
-                            var_stack.pop_back();
-                            f64::min(value.into(), value2.into())
+                        Function::User(fun) => {
+                            self.call_user_function(heap, &name, &fun, args, span)?
                         }
-                        MathFunction::Sqrt => f64::sqrt(value.into()),
-                        MathFunction::Floor => f64::floor(value.into()),
-                        MathFunction::Ceil => f64::ceil(value.into()),
-                        MathFunction::Round => f64::round(value.into()),
-                        MathFunction::Pdf => {
-                            let normal = Normal::new(0.0, 1.0).expect("valid normal dist");
-                            normal.pdf(value.into())
+                        Function::Native { fun, .. } => {
+                            fun(&args).map_err(|e| e.with_span(span))?
                         }
-                        MathFunction::Cdf => {
-                            let normal = Normal::new(0.0, 1.0).expect("valid normal dist");
-                            normal.cdf(value.into())
-                        }
-                        MathFunction::Exp => f64::exp(value.into()),
-                        MathFunction::None => return Err(anyhow!("This should never happen!")),
                     };
-                    result_stack.push_back(Number::DecimalNumber(
-                        BigRational::from_float(res).expect("valid float"),
-                    ));
+                    result_stack.push_back(res);
                     var_stack.push_back(None);
                 }
                 Token::SemiColon => {
-                    result_stack.clear();
                     var_stack.clear();
+                    result_stack.clear();
                 }
-                _ => {
-                    return Err(anyhow!(
-                        "{} Internal Error at line: {}.",
-                        MALFORMED_ERR,
-                        line!()
-                    ))
-                }
+                Token::Bracket(_) => return Err(YarerError::UnbalancedBracket { span }),
+                Token::Comma => return Err(Self::unexpected(t, span)),
             }
         }
-        var_stack.pop_front();
-        result_stack.pop_front().ok_or(anyhow!("{}", MALFORMED_ERR))
+        result_stack
+            .pop_front()
+            .ok_or(YarerError::Malformed { span: self.expr_span() })
     }
 
-    /// Transforming an infix notation to Reverse Polish Notation (RPN)
+    /// Applies an [Operator] to its operands. The left operand of an assignment
+    /// is the variable `left_var`, that can't be one of the results
     ///
-    /// Example
-    /// ``
-    ///     "3 * 4 + 5 * 6" becomes "3 4 * 5 6 * +"
-    /// ``
-    fn reverse_polish_notation<'a>(
-        infix_stack: &[Token<'a>],
-        local_heap: Rc<RefCell<HashMap<String, Number>>>,
-    ) -> (VecDeque<Token<'a>>, Rc<RefCell<HashMap<String, Number>>>) {
-        /*  Create an empty stack for keeping operators. Create an empty list for output. */
-        let mut operators_stack: Vec<Token> = Vec::new();
-        let mut postfix_stack: VecDeque<Token> = VecDeque::new();
-
-        /* Scan the infix expression from left to right. */
-        for t in infix_stack {
-            match *t {
-                /* If the token is an operand, add it to the output list. */
-                Token::Operand(_) => postfix_stack.push_back(t.clone()),
-
-                /* If the token is a left parenthesis, push it on the stack. */
-                Token::Bracket(token::Bracket::Open) => operators_stack.push(t.clone()),
-
-                /* If the token is a right parenthesis:
-                Pop the stack and add operators to the output list until you encounter a left parenthesis.
-                Pop the left parenthesis from the stack but do not add it to the output list.*/
-                Token::Bracket(token::Bracket::Close) => {
-                    while let Some(token) = operators_stack.pop() {
-                        match token {
-                            Token::Bracket(token::Bracket::Open) => {
-                                // If the token is a left parenthesis, pop it from the stack
-                                if let Some(Token::Function(_)) = operators_stack.last() {
-                                    postfix_stack.push_back(
-                                        operators_stack.pop().expect("It should not happen."),
-                                    );
-                                }
-                                break;
-                            } // discards left parenthesis
-                            _ => postfix_stack.push_back(token),
-                        }
-                    }
+    fn apply_operator(
+        &self,
+        heap: &mut HashMap<String, Number>,
+        op: Operator,
+        (left_value, right_value): (Number, Number),
+        left_var: Option<(String, Span)>,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        match op {
+            Operator::Add => Ok(left_value + right_value),
+            Operator::Sub => Ok(left_value - right_value),
+            Operator::Mul => Ok(left_value * right_value),
+            Operator::Div => {
+                if right_value.is_zero() {
+                    return Err(YarerError::DivisionByZero { span });
                 }
-
-                Token::Comma => {
-                    while let Some(token) = operators_stack.last() {
-                        if matches!(token, Token::Bracket(token::Bracket::Open)) {
-                            break;
-                        }
-                        postfix_stack.push_back(operators_stack.pop().expect("It should not happen."));
-                    }
+                Ok(left_value / right_value)
+            }
+            Operator::Pow => {
+                let settings = read(&self.settings);
+                Self::pow(left_value, right_value, settings.precision, settings.complex, span)
+            }
+            Operator::Eql => {
+                // the results are read-only
+                if let Some((var, _)) = left_var.filter(|(var, _)| !var.starts_with('$')) {
+                    self.assign(heap, &var, right_value.clone());
+                    Ok(right_value)
+                } else {
+                    Err(YarerError::InvalidAssignment { span })
                 }
+            }
+            Operator::Fac => Self::factorial(right_value, span),
+            //# unary neg
+            Operator::Une => Ok(right_value * Number::NaturalNumber(BigInt::from(-1))),
+            Operator::Eq => Ok(Number::from(Self::equal(left_value, right_value))),
+            Operator::Ne => Ok(Number::from(!Self::equal(left_value, right_value))),
+            Operator::Lt => Self::ordering(&left_value, &right_value, span).map(|o| Number::from(o.is_lt())),
+            Operator::Le => Self::ordering(&left_value, &right_value, span).map(|o| Number::from(o.is_le())),
+            Operator::Gt => Self::ordering(&left_value, &right_value, span).map(|o| Number::from(o.is_gt())),
+            Operator::Ge => Self::ordering(&left_value, &right_value, span).map(|o| Number::from(o.is_ge())),
+            // both the operands are evaluated, there's no short-circuit
+            Operator::And => Ok(Number::from(!left_value.is_zero() && !right_value.is_zero())),
+            Operator::Or => Ok(Number::from(!left_value.is_zero() || !right_value.is_zero())),
+            Operator::Not => Ok(Number::from(right_value.is_zero())),
+        }
+    }
 
-                Token::SemiColon => {
-                    while let Some(token) = operators_stack.pop() {
-                        postfix_stack.push_back(token);
-                    }
-                    postfix_stack.push_back(Token::SemiColon);
-                }
+    /// Compares two [Number]s by their values, so '1 == 1.0' even if one is natural and the other decimal
+    ///
+    fn equal(left: Number, right: Number) -> bool {
+        Complex::<BigRational>::from(left) == Complex::<BigRational>::from(right)
+    }
 
-                Token::Operator(_op) => {
-                    let op1: Token<'_> = t.clone();
-
-                    while !operators_stack.is_empty() {
-                        let op2: &Token = operators_stack.last().unwrap();
-                        match op2 {
-                            Token::Operator(_) => {
-                                if Token::compare_operator_priority(op1.clone(), op2.clone()) {
-                                    postfix_stack.push_back(
-                                        operators_stack.pop().expect("It should not happen."),
-                                    );
-                                } else {
-                                    break;
-                                }
-                            }
-                            Token::Function(_) => {
-                                postfix_stack.push_back(
-                                    operators_stack.pop().expect("It should not happen."),
-                                );
-                            }
-                            _ => break,
-                        }
+    /// Orders two real [Number]s: the complex ones can't be ordered
+    ///
+    fn ordering(left: &Number, right: &Number, span: Span) -> Result<Ordering, YarerError> {
+        left.partial_cmp(right).ok_or(YarerError::NotOrdered { span })
+    }
+
+    /// Evaluates the compiled body of a [`UserFunction`] with its parameters bound to `args`.
+    /// The parameters, and the variables assigned by the body, live in a frame read before the heap,
+    /// so they don't clobber the session variables. Any error is reported at the `span` of the call
+    ///
+    fn call_user_function(
+        &self,
+        heap: &mut HashMap<String, Number>,
+        name: &str,
+        fun: &UserFunction,
+        args: Vec<Number>,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        if self.depth >= CALL_DEPTH_LIMIT {
+            return Err(YarerError::RecursionLimit {
+                function: name.to_string(),
+                span,
+            });
+        }
+        let mut resolver = self.nested(fun.body.rpn());
+        resolver.frame = Some(shared(fun.params.iter().cloned().zip(args).collect()));
+        resolver.depth = self.depth + 1;
+        resolver.resolve_in(heap).map_err(|e| e.with_span(span))
+    }
+
+    /// The [Span] covering all the tokens of the expression
+    ///
+    fn expr_span(&self) -> Span {
+        let start = self.rpn_expr.iter().map(|(_, s)| s.start).min();
+        let end = self.rpn_expr.iter().map(|(_, s)| s.end).max();
+        Span::new(start.unwrap_or_default(), end.unwrap_or_default())
+    }
+
+    /// Sets a variable: a value replaces the formula of a cell, and updates its dependents.
+    /// In the body of a user function it sets a local variable of the frame
+    ///
+    fn assign(&self, heap: &mut HashMap<String, Number>, var: &str, value: Number) {
+        if let Some(frame) = &self.frame {
+            write(frame).insert(var.to_string(), value);
+            return;
+        }
+        heap.insert(var.to_string(), value);
+        write(&self.cells).remove(var);
+        cell::recompute(var, &self.cells, heap, |rpn_expr| self.nested(rpn_expr));
+    }
+
+    /// The value of a variable, or of a result (`$1` is the first one)
+    ///
+    fn lookup(&self, heap: &HashMap<String, Number>, name: &str) -> Option<Number> {
+        match name.strip_prefix('$') {
+            Some(n) => {
+                let index = n.parse::<usize>().ok()?.checked_sub(1)?;
+                read(&self.results).get(index).cloned()
+            }
+            None => self
+                .frame
+                .as_ref()
+                .and_then(|frame| read(frame).get(name).cloned())
+                .or_else(|| heap.get(name).cloned()),
+        }
+    }
+
+    /// Finds out which variables of the RPN expression are the left side of an assignment,
+    /// the only ones that can be undefined in strict mode: 'x' in 'x = 1', but not in 'x + (x = 1)'
+    ///
+    fn assignment_targets(rpn_expr: &[(Token<'_>, Span)]) -> Vec<bool> {
+        let mut targets = vec![false; rpn_expr.len()];
+        // the index of the variable each value on the stack has been read from, if any
+        let mut stack: Vec<Option<usize>> = Vec::new();
+        for (i, (t, _)) in rpn_expr.iter().enumerate() {
+            match t {
+                Token::Variable(_) => stack.push(Some(i)),
+                Token::Operator(op) => {
+                    stack.pop();
+                    let left = if op.is_unary() { None } else { stack.pop().flatten() };
+                    if let (Operator::Eql, Some(left)) = (op, left) {
+                        targets[left] = true;
                     }
-                    operators_stack.push(op1.clone());
+                    stack.push(None);
+                }
+                Token::Function(_, found) | Token::UserFunction(_, found) => {
+                    stack.truncate(stack.len().saturating_sub(*found));
+                    stack.push(None);
                 }
+                Token::SemiColon => stack.clear(),
+                _ => stack.push(None),
+            }
+        }
+        targets
+    }
+
+    fn unexpected(t: &Token, span: Span) -> YarerError {
+        YarerError::UnexpectedToken {
+            token: t.symbol(),
+            span,
+        }
+    }
 
-                Token::Function(_) => {
-                    operators_stack.push(t.clone());
+    fn domain_error(op: Operator, span: Span) -> YarerError {
+        YarerError::DomainError {
+            function: Token::Operator(op).symbol(),
+            span,
+        }
+    }
+
+    /// Evaluates a [`MathFunction`] on a value. In `complex` mode the real values out of the
+    /// domain of the function (i.e. 'sqrt(-1)') are evaluated in the complex plane
+    ///
+    fn function(
+        fun: MathFunction,
+        value: Number,
+        precision: Option<u64>,
+        complex: bool,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        if value.is_complex() {
+            return Self::complex_function(fun, value, precision, span);
+        }
+        let fallback = complex.then(|| value.clone());
+        match (Self::real_function(fun, value, precision, span), fallback) {
+            (Err(YarerError::DomainError { .. }), Some(value)) => {
+                Self::complex_function(fun, value, precision, span)
+            }
+            (res, _) => res.map(Number::DecimalNumber),
+        }
+    }
+
+    /// Evaluates a [`MathFunction`] on a real value, with `digits` significant digits in precision mode
+    ///
+    fn real_function(
+        fun: MathFunction,
+        value: Number,
+        precision: Option<u64>,
+        span: Span,
+    ) -> Result<BigRational, YarerError> {
+        match precision {
+            Some(digits) => Self::precise_function(fun, value, digits).ok_or_else(|| match fun {
+                // too large arguments, not out of the domain
+                MathFunction::Exp | MathFunction::Sin | MathFunction::Cos | MathFunction::Tan => {
+                    YarerError::Overflow { span }
                 }
+                _ => YarerError::DomainError {
+                    function: Token::Function(fun, 1).symbol(),
+                    span,
+                },
+            }),
+            None => Self::float_function(fun, value, span),
+        }
+    }
 
-                /* If the token is a variable, add it to the output list and to the local_heap with a default value*/
-                Token::Variable(s) => {
-                    postfix_stack.push_back(t.clone());
-                    let s = s.to_lowercase();
-                    local_heap
-                        .borrow_mut()
-                        .entry(s) // let's not override consts
-                        .or_insert(Number::NaturalNumber(Zero::zero()));
+    /// Evaluates a [`MathFunction`] in the complex plane, taking its principal value.
+    /// The square root and the logarithms of a negative number are computed from the real
+    /// functions, so '2i' is exact; the other functions are computed with f64 arithmetic
+    ///
+    fn complex_function(
+        fun: MathFunction,
+        value: Number,
+        precision: Option<u64>,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        let domain_error = || YarerError::DomainError {
+            function: Token::Function(fun, 1).symbol(),
+            span,
+        };
+        let z = Complex::<BigRational>::from(value);
+        if z.im.is_zero() && z.re.is_negative() {
+            let x = Number::DecimalNumber(-z.re.clone());
+            // i.e. ln(-x) = ln(x) + iπ, and log(-x) = log(x) + iπ/ln(10)
+            let pi = || match precision {
+                Some(digits) => {
+                    let big = BigMath::new(digits);
+                    big.to_rational(&big.pi())
+                }
+                None => BigRational::from_float(std::f64::consts::PI).unwrap_or_default(),
+            };
+            let parts = match fun {
+                MathFunction::Sqrt => Some((BigRational::zero(), Self::real_function(fun, x, precision, span)?)),
+                MathFunction::Ln => Some((Self::real_function(fun, x, precision, span)?, pi())),
+                MathFunction::Log => {
+                    let ln10 = Self::real_function(MathFunction::Ln, Number::NaturalNumber(10.into()), precision, span)?;
+                    Some((Self::real_function(fun, x, precision, span)?, pi() / ln10))
                 }
+                _ => None,
+            };
+            if let Some((re, im)) = parts {
+                return Ok(Number::from_complex(Complex::new(re, im)));
             }
-            debug!(
-                "Inspecting... {} - OUT {} - OP - {}",
-                *t,
-                DisplayThisDeque(&postfix_stack),
-                DisplayThatVec(&operators_stack)
-            );
         }
+        let w = Complex64::from(Number::ComplexNumber(z.clone()));
+        let res = match fun {
+            MathFunction::Sin => w.sin(),
+            MathFunction::Cos => w.cos(),
+            MathFunction::Tan => w.tan(),
+            MathFunction::ASin => w.asin(),
+            MathFunction::ACos => w.acos(),
+            MathFunction::ATan => w.atan(),
+            MathFunction::Ln => w.ln(),
+            MathFunction::Log => w.log10(),
+            MathFunction::Sqrt => w.sqrt(),
+            MathFunction::Exp => w.exp(),
+            // the modulus
+            MathFunction::Abs => {
+                let modulus = Self::real_function(MathFunction::Sqrt, Number::DecimalNumber(z.norm_sqr()), precision, span)?;
+                return Ok(Number::DecimalNumber(modulus));
+            }
+            // each part is rounded on its own, exactly
+            MathFunction::Floor => return Ok(Number::from_complex(Complex::new(z.re.floor(), z.im.floor()))),
+            MathFunction::Ceil => return Ok(Number::from_complex(Complex::new(z.re.ceil(), z.im.ceil()))),
+            MathFunction::Round => return Ok(Number::from_complex(Complex::new(z.re.round(), z.im.round()))),
+            MathFunction::Pdf
+            | MathFunction::Cdf
+            | MathFunction::Max
+            | MathFunction::Min
+            | MathFunction::Sum
+            | MathFunction::Avg
+            | MathFunction::Hypot
+            | MathFunction::None => return Err(domain_error()),
+        };
+        // NaN or infinite results can't be represented as rationals
+        Number::from_complex64(res).ok_or_else(|| {
+            if res.is_infinite() && !matches!(fun, MathFunction::Ln | MathFunction::Log) {
+                YarerError::Overflow { span }
+            } else {
+                domain_error()
+            }
+        })
+    }
 
-        /* After all tokens are read, pop remaining operators from the stack and add them to the list. */
-        operators_stack.reverse();
-        operators_stack
-            .iter()
-            .for_each(|t| postfix_stack.push_back(t.clone()));
-
-        debug!(
-            "DEBUG: EOF - OUT {} - OP - {}",
-            DisplayThisDeque(&postfix_stack),
-            DisplayThatVec(&operators_stack)
-        );
+    /// Evaluates a [`MathFunction`] with the f64 math of the standard library
+    ///
+    fn float_function(fun: MathFunction, value: Number, span: Span) -> Result<BigRational, YarerError> {
+        let x: f64 = value.into();
+        if !x.is_finite() {
+            return Err(YarerError::Overflow { span });
+        }
+        let res = match fun {
+            MathFunction::Sin => f64::sin(x),
+            MathFunction::Cos => f64::cos(x),
+            MathFunction::Tan => f64::tan(x),
+            MathFunction::ASin => f64::asin(x),
+            MathFunction::ACos => f64::acos(x),
+            MathFunction::ATan => f64::atan(x),
+            MathFunction::Ln => f64::ln(x),
+            MathFunction::Log => f64::log10(x),
+            MathFunction::Abs => f64::abs(x),
+            MathFunction::Sqrt => f64::sqrt(x),
+            MathFunction::Floor => f64::floor(x),
+            MathFunction::Ceil => f64::ceil(x),
+            MathFunction::Round => f64::round(x),
+            MathFunction::Pdf => {
+                let normal = Normal::new(0.0, 1.0).expect("valid normal dist");
+                normal.pdf(x)
+            }
+            MathFunction::Cdf => {
+                let normal = Normal::new(0.0, 1.0).expect("valid normal dist");
+                normal.cdf(x)
+            }
+            MathFunction::Exp => f64::exp(x),
+            MathFunction::Max
+            | MathFunction::Min
+            | MathFunction::Sum
+            | MathFunction::Avg
+            | MathFunction::Hypot
+            | MathFunction::None => f64::NAN,
+        };
+        // NaN or infinite results can't be represented as rationals
+        BigRational::from_float(res).ok_or_else(|| {
+            if res.is_infinite() && !matches!(fun, MathFunction::Ln | MathFunction::Log) {
+                YarerError::Overflow { span }
+            } else {
+                YarerError::DomainError {
+                    function: Token::Function(fun, 1).symbol(),
+                    span,
+                }
+            }
+        })
+    }
 
-        (postfix_stack, local_heap)
+    /// Evaluates a [`MathFunction`] with `digits` significant digits using [`BigMath`]
+    ///
+    fn precise_function(fun: MathFunction, value: Number, digits: u64) -> Option<BigRational> {
+        BigMath::new(digits).apply(fun, &BigRational::from(value))
     }
 
-    fn factorial_helper(n: BigUint) -> BigUint {
-        if n == BigUint::zero() {
-            return BigUint::one();
+    /// Evaluates a variadic [`MathFunction`] on one or more values. Max, min, sum and avg are exact,
+    /// hypot is computed with f64 arithmetic or, in precision mode, with `digits` significant digits.
+    /// The complex values can be added, but they can't be ordered
+    ///
+    fn variadic_function(
+        fun: MathFunction,
+        args: Vec<Number>,
+        precision: Option<u64>,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        let ordered = !args.iter().any(Number::is_complex);
+        let values: Vec<Complex<BigRational>> = args.into_iter().map(Complex::from).collect();
+        let malformed = || YarerError::Malformed { span };
+        let sum = || values.iter().fold(Complex::zero(), |acc, v| acc + v);
+        let number = |c: Complex<BigRational>| {
+            if c.im.is_zero() {
+                Number::DecimalNumber(c.re)
+            } else {
+                Number::ComplexNumber(c)
+            }
+        };
+        let reals = values.iter().map(|v| &v.re);
+        match fun {
+            MathFunction::Max | MathFunction::Min if !ordered => Err(YarerError::NotOrdered { span }),
+            MathFunction::Max => reals.max().cloned().map(Number::DecimalNumber).ok_or_else(malformed),
+            MathFunction::Min => reals.min().cloned().map(Number::DecimalNumber).ok_or_else(malformed),
+            MathFunction::Sum => Ok(number(sum())),
+            MathFunction::Avg => {
+                if values.is_empty() {
+                    return Err(malformed());
+                }
+                let count = BigRational::from_integer(values.len().into());
+                Ok(number(sum() / count))
+            }
+            MathFunction::Hypot => {
+                let squares = values.iter().fold(BigRational::zero(), |acc, v| acc + v.norm_sqr());
+                let res = match precision {
+                    Some(digits) => BigMath::new(digits)
+                        .apply(MathFunction::Sqrt, &squares)
+                        .ok_or(YarerError::Overflow { span }),
+                    None => Self::float_function(
+                        MathFunction::Sqrt,
+                        Number::DecimalNumber(squares),
+                        span,
+                    ),
+                };
+                res.map(Number::DecimalNumber)
+            }
+            _ => Err(malformed()),
         }
+    }
 
-        let previous = n.clone() - BigUint::one();
-        let sub_result = RpnResolver::factorial_helper(previous);
-        n * sub_result
+    /// Raises a [Number] to a power. In `complex` mode a negative base has a complex root,
+    /// otherwise it's out of the domain
+    ///
+    fn pow(
+        base: Number,
+        exponent: Number,
+        precision: Option<u64>,
+        complex: bool,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        let complex_operands = base.is_complex() || exponent.is_complex();
+        if let Some(digits) = precision.filter(|_| !complex_operands) {
+            return Self::precise_pow(base, exponent, digits, complex, span);
+        }
+        let zero = Number::NaturalNumber(BigInt::zero());
+        if exponent < zero && base.is_zero() {
+            return Err(YarerError::DivisionByZero { span });
+        }
+        // a negative base has no real root, but it has a complex one
+        let no_real_root = base < zero && !BigRational::from(exponent.clone()).is_integer();
+        let res = if no_real_root && complex {
+            base.complex_pow(exponent)
+        } else {
+            base.checked_pow(exponent)
+        };
+        res.ok_or_else(|| {
+            if no_real_root && !complex {
+                Self::domain_error(Operator::Pow, span)
+            } else {
+                YarerError::Overflow { span }
+            }
+        })
     }
-}
 
-struct DisplayThatVec<'a>(&'a Vec<Token<'a>>);
-struct DisplayThisDeque<'a>(&'a VecDeque<Token<'a>>);
+    /// Raises a [Number] to a power in precision mode: integer exponents are exact,
+    /// the others are computed by [`BigMath`], or with f64 arithmetic if the root is `complex`
+    ///
+    fn precise_pow(
+        base: Number,
+        exponent: Number,
+        digits: u64,
+        complex: bool,
+        span: Span,
+    ) -> Result<Number, YarerError> {
+        let exponent = BigRational::from(exponent);
+        if exponent.is_negative() && base.is_zero() {
+            return Err(YarerError::DivisionByZero { span });
+        }
+        if exponent.is_integer() {
+            let e = exponent.to_integer();
+            if matches!(base, Number::NaturalNumber(_)) && !e.is_negative() {
+                return base
+                    .checked_pow(Number::NaturalNumber(e))
+                    .ok_or(YarerError::Overflow { span });
+            }
+            let base = BigRational::from(base);
+            let bits = base.numer().bits().max(base.denom().bits());
+            return e
+                .to_i32()
+                .filter(|e| token::pow_within_limit(bits, u64::from(e.unsigned_abs())))
+                .map(|e| Number::DecimalNumber(base.pow(e)))
+                .ok_or(YarerError::Overflow { span });
+        }
+        let no_real_root = base < Number::NaturalNumber(BigInt::zero());
+        if no_real_root && complex {
+            return base.complex_pow(Number::DecimalNumber(exponent)).ok_or(YarerError::Overflow { span });
+        }
+        BigMath::new(digits)
+            .pow(&base.into(), &exponent)
+            .map(Number::DecimalNumber)
+            .ok_or_else(|| {
+                if no_real_root {
+                    Self::domain_error(Operator::Pow, span)
+                } else {
+                    YarerError::Overflow { span }
+                }
+            })
+    }
 
-impl Display for DisplayThatVec<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.0.iter().map(ToString::to_string).collect::<String>()
-        )
+    /// The factorial of a [Number]. Only for non-negative integers
+    ///
+    fn factorial(value: Number, span: Span) -> Result<Number, YarerError> {
+        match value {
+            Number::NaturalNumber(v) => {
+                if v < Zero::zero() {
+                    return Err(Self::domain_error(Operator::Fac, span));
+                }
+                let n = v
+                    .to_u64()
+                    .filter(|n| *n <= FACTORIAL_LIMIT)
+                    .ok_or(YarerError::Overflow { span })?;
+                Ok(Number::NaturalNumber(Self::factorial_helper(n).into()))
+            }
+            Number::DecimalNumber(_) | Number::ComplexNumber(_) => {
+                Err(Self::domain_error(Operator::Fac, span))
+            }
+        }
     }
-}
 
-impl Display for DisplayThisDeque<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.0.iter().map(ToString::to_string).collect::<String>()
-        )
+    fn factorial_helper(n: u64) -> BigUint {
+        (2..=n).fold(BigUint::one(), |acc, i| acc * i)
     }
 }
 
@@ -409,28 +832,10 @@ mod tests {
     };
     use num_bigint::{BigInt, BigUint};
 
-    #[test]
-    fn test_reverse_polish_notation() {
-        let a: Vec<Token> = vec![
-            Token::Operand(Number::NaturalNumber(BigInt::from(1u8))),
-            Token::Operator(Operator::Add),
-            Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
-        ];
-        let b: Vec<Token> = vec![
-            Token::Operand(Number::NaturalNumber(BigInt::from(1u8))),
-            Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
-            Token::Operator(Operator::Add),
-        ];
-        assert_eq!(
-            RpnResolver::reverse_polish_notation(&a, Rc::new(RefCell::new(HashMap::new()))).0,
-            b
-        );
-    }
-
     #[test]
     fn test_factorial() {
         assert_eq!(
-            RpnResolver::factorial_helper(BigUint::from(5u8)),
+            RpnResolver::factorial_helper(5),
             BigUint::from(120u16)
         );
     }
@@ -438,12 +843,20 @@ mod tests {
     #[test]
     fn test_resolve() {
         let mut resolver = RpnResolver {
-            rpn_expr: VecDeque::from(vec![
-                Token::Operand(Number::NaturalNumber(BigInt::from(1u8))),
-                Token::Operand(Number::NaturalNumber(BigInt::from(2u8))),
-                Token::Operator(Operator::Add),
+            rpn_expr: Cow::Owned(vec![
+                (Token::Operand(Number::NaturalNumber(BigInt::from(1u8))), Span::default()),
+                (Token::Operand(Number::NaturalNumber(BigInt::from(2u8))), Span::default()),
+                (Token::Operator(Operator::Add), Span::default()),
             ]),
-            local_heap: Rc::new(RefCell::new(HashMap::new())),
+            parse_errors: Vec::new(),
+            local_heap: shared(HashMap::new()),
+            frame: None,
+            settings: shared(Settings::default()),
+            functions: shared(HashMap::new()),
+            cells: shared(BTreeMap::new()),
+            results: shared(Vec::new()),
+            record: false,
+            depth: 0,
         };
         assert_eq!(
             resolver.resolve().unwrap(),
@@ -460,6 +873,52 @@ mod tests {
         assert!(resolver2.resolve().is_err());
     }
 
+    #[test]
+    fn test_error_spans() {
+        let session = Session::init();
+        assert_eq!(
+            session.process("1 + 2/0").resolve(),
+            Err(YarerError::DivisionByZero {
+                span: Span::new(5, 6)
+            })
+        );
+        assert_eq!(
+            session.process("2 * (3 + 1").resolve(),
+            Err(YarerError::UnbalancedBracket {
+                span: Span::new(4, 5)
+            })
+        );
+        assert_eq!(
+            session.process("sqrt(1, 2)").resolve(),
+            Err(YarerError::ArityMismatch {
+                function: "sqrt".to_string(),
+                expected: Arity::Exact(1),
+                found: 2,
+                span: Span::new(0, 4)
+            })
+        );
+        assert_eq!(
+            session.process("1 +").resolve(),
+            Err(YarerError::UnexpectedToken {
+                token: "+".to_string(),
+                span: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            session.process("3 = 2").resolve(),
+            Err(YarerError::InvalidAssignment {
+                span: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            session.process("(-1)!").resolve(),
+            Err(YarerError::DomainError {
+                function: "!".to_string(),
+                span: Span::new(4, 5)
+            })
+        );
+    }
+
     #[test]
     fn test_max_min() {
         let session = Session::init();
@@ -1,6 +1,79 @@
-use crate::{rpn_resolver::RpnResolver, token::Number};
+use crate::{
+    bigmath::BigMath,
+    cell::{self, Cell, Cells},
+    compiled::CompiledExpression,
+    error::{Span, YarerError},
+    format::{Notation, NumberFormat},
+    function::{Arity, Function, Functions, NativeClosure, UserFunction},
+    parser::Parser,
+    rpn_resolver::{Results, RpnResolver},
+    shared::{read, shared, write, Ptr, Shared},
+    token::{Number, Token},
+};
+use num::complex::Complex;
+use num::{One, Zero};
 use num_bigint::BigInt;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use num_rational::BigRational;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    fs, io,
+    path::Path,
+};
+
+/// The names of the built-in constants, defined in every [`Session`]
+///
+pub const CONSTANTS: [&str; 5] = ["pi", "e", "tau", "phi", "gamma"];
+
+/// The imaginary unit, a built-in constant of the sessions in complex mode
+///
+pub const IMAGINARY_UNIT: &str = "i";
+
+/// The variable holding the result of the last expression resolved by a [`Session`]
+///
+pub const ANS: &str = "ans";
+
+/// The maximum number of significant digits of the precision mode: the series computing
+/// the constants and the math functions slow down with the cube of the digits, and a thousand
+/// of them already take seconds
+///
+pub const MAX_PRECISION: u64 = 1000;
+
+/// The first line of a file written by [`Session::save`]
+///
+const SESSION_HEADER: &str = "# yarer session";
+
+/// The configuration of a [`Session`], shared with all the [`RpnResolver`] instances built using [`Session::process()`]
+///
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// The number of significant digits computed by the math functions,
+    /// or [`None`] to use the (faster) f64 arithmetic
+    pub precision: Option<u64>,
+    /// If true (the default), reading an undefined variable is an error,
+    /// otherwise the variable evaluates to zero
+    pub strict: bool,
+    /// How the results are formatted by [`Session::format_number`]
+    pub format: NumberFormat,
+    /// If true, the imaginary unit [`IMAGINARY_UNIT`] is defined and the functions and powers
+    /// out of the real domain (i.e. 'sqrt(-1)') return a complex number instead of an error
+    pub complex: bool,
+    /// If true (the default), the multiplications can be left implicit, as in '2x' or '(a+b)(a-b)',
+    /// otherwise every multiplication needs its '*'
+    pub implicit: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            precision: None,
+            strict: true,
+            format: NumberFormat::default(),
+            complex: false,
+            implicit: true,
+        }
+    }
+}
 
 /// A [`Session`] is an object that holds a variable heap in the form of a [`HashMap`]
 /// that is borrowed to all the [`RpnResolver`] instances built using [`process()`]
@@ -8,7 +81,11 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 /// Example
 ///
 pub struct Session {
-    variable_heap: Rc<RefCell<HashMap<String, Number>>>,
+    variable_heap: Shared<HashMap<String, Number>>,
+    settings: Shared<Settings>,
+    functions: Functions,
+    cells: Cells,
+    results: Results,
 }
 
 impl Session {
@@ -28,16 +105,721 @@ impl Session {
     pub fn init() -> Session {
         // let variable_heap: HashMap<String, Number> = ;
         Session {
-            variable_heap: Rc::new(RefCell::new(Session::init_local_heap())),
+            variable_heap: shared(Session::init_local_heap()),
+            settings: shared(Settings::default()),
+            functions: shared(HashMap::new()),
+            cells: shared(BTreeMap::new()),
+            results: shared(Vec::new()),
+        }
+    }
+
+    /// Builds a new [`Session`] in precision mode, where the math functions
+    /// and the built-in constants are computed with `digits` significant digits
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::with_precision(50).unwrap();
+    ///      let mut resolver = session.process("sqrt(2)");
+    ///      let result = resolver.resolve().unwrap();
+    ///      assert!(result.to_precise_string(50).starts_with("1.4142135623730950488016887242096980785696718753769"));
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// [`YarerError::PrecisionLimit`] if `digits` is more than [`MAX_PRECISION`]
+    ///
+    pub fn with_precision(digits: u64) -> Result<Session, YarerError> {
+        let session = Session::init();
+        session.set_precision(Some(digits))?;
+        Ok(session)
+    }
+
+    /// Switches the precision mode on (with `Some(digits)` significant digits) or off (with [`None`]).
+    /// The built-in constants are regenerated with the new precision, except the ones
+    /// that have been assigned another value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.process("pi = 3").resolve().unwrap();
+    ///      session.set_precision(Some(30)).unwrap();
+    ///      assert_eq!(session.process("pi").resolve().unwrap().to_string(), "3");
+    ///      assert_eq!(session.process("e").resolve().unwrap().to_precise_string(25), "2.718281828459045235360287");
+    ///      assert!(session.set_precision(Some(1_000_000)).is_err());
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// [`YarerError::PrecisionLimit`] if the digits are more than [`MAX_PRECISION`]:
+    /// the precision of the session is left unchanged
+    ///
+    pub fn set_precision(&self, digits: Option<u64>) -> Result<(), YarerError> {
+        if let Some(digits) = digits.filter(|&d| d > MAX_PRECISION) {
+            return Err(YarerError::PrecisionLimit {
+                digits,
+                max: MAX_PRECISION,
+                span: Span::default(),
+            });
+        }
+        let previous = self.constants();
+        write(&self.settings).precision = digits;
+        let constants = self.constants();
+        let mut regenerated = Vec::new();
+        {
+            let mut heap = write(&self.variable_heap);
+            for (name, value) in constants {
+                // a constant still holding its previous value hasn't been reassigned
+                if heap.get(&name) == previous.get(&name) {
+                    heap.insert(name.clone(), value);
+                    regenerated.push(name);
+                }
+            }
+        }
+        for constant in regenerated {
+            self.recompute(&constant);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of significant digits of the precision mode, if it's on
+    ///
+    #[must_use]
+    pub fn precision(&self) -> Option<u64> {
+        read(&self.settings).precision
+    }
+
+    /// Switches the strict mode on or off. In strict mode (the default) reading a variable
+    /// that has never been defined is a [`crate::error::YarerError::UnknownVariable`] error,
+    /// otherwise the variable evaluates to zero
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::{error::YarerError, session::Session};
+    ///
+    ///      let session = Session::init();
+    ///      let res = session.process("pirce * 3").resolve();
+    ///      assert!(matches!(res, Err(YarerError::UnknownVariable { .. })));
+    ///
+    ///      session.set_strict(false);
+    ///      assert_eq!(session.process("pirce * 3").resolve().unwrap().to_string(), "0");
+    ///  ```
+    ///
+    pub fn set_strict(&self, strict: bool) {
+        write(&self.settings).strict = strict;
+    }
+
+    /// Returns true if the strict mode is on
+    ///
+    #[must_use]
+    pub fn is_strict(&self) -> bool {
+        read(&self.settings).strict
+    }
+
+    /// Switches the implicit multiplication on (the default) or off. When it's off, the
+    /// expressions are parsed strictly and '2x' is a [`crate::error::YarerError::UnexpectedToken`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.set("x", 3);
+    ///      assert_eq!(session.process("2(x + 1)x").resolve().unwrap().to_string(), "24");
+    ///
+    ///      session.set_implicit(false);
+    ///      assert!(session.process("2x").resolve().is_err());
+    ///  ```
+    ///
+    pub fn set_implicit(&self, implicit: bool) {
+        write(&self.settings).implicit = implicit;
+    }
+
+    /// Returns true if the multiplications can be left implicit
+    ///
+    #[must_use]
+    pub fn is_implicit(&self) -> bool {
+        read(&self.settings).implicit
+    }
+
+    /// Switches the complex mode on or off. In complex mode the imaginary unit 'i' is defined,
+    /// and the square roots, logarithms and powers of negative numbers are complex numbers
+    /// instead of [`crate::error::YarerError::DomainError`] errors. Neither switching it on nor off
+    /// changes 'i' if it has been assigned another value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      assert!(session.process("sqrt(-4)").resolve().is_err());
+    ///
+    ///      session.set_complex(true);
+    ///      assert_eq!(session.process("sqrt(-4)").resolve().unwrap().to_string(), "2i");
+    ///      assert_eq!(session.process("(1 + i) * (1 - i)").resolve().unwrap().to_string(), "2");
+    ///  ```
+    ///
+    pub fn set_complex(&self, complex: bool) {
+        write(&self.settings).complex = complex;
+        {
+            let mut heap = write(&self.variable_heap);
+            if complex {
+                heap.entry(IMAGINARY_UNIT.to_string()).or_insert_with(Session::imaginary_unit);
+            } else if heap.get(IMAGINARY_UNIT) == Some(&Session::imaginary_unit()) {
+                heap.remove(IMAGINARY_UNIT);
+            }
         }
+        self.recompute(IMAGINARY_UNIT);
+    }
+
+    /// Returns true if the complex mode is on
+    ///
+    #[must_use]
+    pub fn is_complex(&self) -> bool {
+        read(&self.settings).complex
     }
 
-    /// The [`RpnResolver`] single line builder. It needs the math expression to process
+    /// Sets the [`NumberFormat`] used by [`Session::format_number`]
+    ///
+    pub fn set_number_format(&self, format: NumberFormat) {
+        write(&self.settings).format = format;
+    }
+
+    /// Returns the [`NumberFormat`] used by [`Session::format_number`]
+    ///
+    #[must_use]
+    pub fn number_format(&self) -> NumberFormat {
+        read(&self.settings).format
+    }
+
+    /// Formats a [`Number`] with the [`NumberFormat`] of the session. In precision mode,
+    /// [`Notation::Auto`] writes all the significant digits of the session instead of the f64 ones
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::{format::{Notation, NumberFormat}, session::Session};
+    ///
+    ///      let session = Session::init();
+    ///      session.set_number_format(NumberFormat::new(Notation::Scientific(3)));
+    ///      let result = session.process("2^100").resolve().unwrap();
+    ///      assert_eq!(session.format_number(&result), "1.27e30");
+    ///  ```
+    ///
+    #[must_use]
+    pub fn format_number(&self, number: &Number) -> String {
+        let settings = read(&self.settings);
+        match (settings.format.notation, settings.precision) {
+            (Notation::Auto, Some(digits)) => settings.format.grouped(&number.to_precise_string(digits)),
+            _ => number.format(&settings.format),
+        }
+    }
+
+    /// The [`RpnResolver`] single line builder. It needs the math expression to process.
+    /// The results aren't recorded: see [`Session::process_recorded`] to read them back
+    /// as `ans`, `$1`, `$2`, ...
     ///
     #[must_use]
     pub fn process<'a>(&'a self, line: &'a str) -> RpnResolver<'a> {
-        let clone = Rc::clone(&self.variable_heap); // clones the Rc pointer, not the whole heap!
-        RpnResolver::parse_with_borrowed_heap(line, clone)
+        let clone = Shared::clone(&self.variable_heap); // clones the pointer, not the whole heap!
+        RpnResolver::parse_with_borrowed_session(
+            line,
+            clone,
+            Shared::clone(&self.settings),
+            Shared::clone(&self.functions),
+            Shared::clone(&self.cells),
+            Shared::clone(&self.results),
+        )
+    }
+
+    /// Same as [`Session::process`], but every result it resolves is stored in the `ans`
+    /// variable, and numbered as `$1`, `$2`, ... Every result is kept until
+    /// [`Session::clear_results`], so it's meant for an interactive session such as the REPL
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.process_recorded("6 * 7").resolve().unwrap();
+    ///      session.process_recorded("ans / 2").resolve().unwrap();
+    ///      let result = session.process_recorded("$1 + $2").resolve().unwrap();
+    ///      assert_eq!(result.to_string(), "63");
+    ///  ```
+    ///
+    #[must_use]
+    pub fn process_recorded<'a>(&'a self, line: &'a str) -> RpnResolver<'a> {
+        self.process(line).recording()
+    }
+
+    /// Builds an [`RpnResolver`] for an expression already converted to RPN
+    ///
+    pub(crate) fn resolver<'a>(&self, rpn_expr: &'a [(Token<'a>, Span)]) -> RpnResolver<'a> {
+        RpnResolver::with_borrowed_rpn(
+            rpn_expr,
+            Shared::clone(&self.variable_heap),
+            Shared::clone(&self.settings),
+            Shared::clone(&self.functions),
+            Shared::clone(&self.cells),
+            Shared::clone(&self.results),
+        )
+    }
+
+    /// Defines (or redefines) a [`UserFunction`] that can be called in any expression
+    /// processed by the session. The parameters are local to the body, and the body
+    /// can call other user functions
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.define_function("hyp", &["a", "b"], "sqrt(a^2 + b^2)").unwrap();
+    ///      let result = session.process("hyp(3, 4)").resolve().unwrap();
+    ///      assert_eq!(result.to_string(), "5");
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// [`YarerError::InvalidDefinition`] if the name is a built-in function or the parameters
+    /// aren't distinct names, [`YarerError::CyclicDefinition`] if a cell calling the function
+    /// would depend on itself through the body, or the first syntax error of the body
+    ///
+    pub fn define_function(&self, name: &str, params: &[&str], body: &str) -> Result<(), YarerError> {
+        self.define(name, params, body, &[name])
+    }
+
+    /// Defines a [`UserFunction`] whose body can call the functions of the session and the ones
+    /// in `defining`: the function itself, or all the functions of a file being loaded
+    ///
+    fn define(&self, name: &str, params: &[&str], body: &str, defining: &[&str]) -> Result<(), YarerError> {
+        let lowercase: Vec<String> = params.iter().map(|p| p.to_lowercase()).collect();
+        let distinct = lowercase
+            .iter()
+            .enumerate()
+            .all(|(i, p)| Session::is_name(p) && !lowercase[..i].contains(p));
+        if !Session::is_name(name) || !distinct {
+            return Err(YarerError::InvalidDefinition {
+                name: name.to_string(),
+                span: Span::default(),
+            });
+        }
+        let body = self.compile(body.trim(), defining)?;
+        let fun = UserFunction::from_compiled(params, body);
+        self.insert_function(name.to_lowercase(), Function::User(fun))
+    }
+
+    /// Registers (or replaces) a native function, implemented by a Rust closure, that can be called
+    /// in any expression processed by the session. The closure receives the values of the arguments,
+    /// whose number is checked against the declared [`Arity`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::{function::Arity, session::Session, token::Number};
+    ///
+    ///      let session = Session::init();
+    ///      session
+    ///          .register_function("count", Arity::AtLeast(0), |args: &[Number]| {
+    ///              Ok(Number::NaturalNumber(args.len().into()))
+    ///          })
+    ///          .unwrap();
+    ///      let result = session.process("count(1, 2, 3) * 2").resolve().unwrap();
+    ///      assert_eq!(result.to_string(), "6");
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// [`YarerError::InvalidDefinition`] if the name is a built-in function,
+    /// [`YarerError::CyclicDefinition`] if it replaces a user function and a cell calling it
+    /// would depend on itself through the bodies of the other functions
+    ///
+    pub fn register_function<F>(&self, name: &str, arity: Arity, fun: F) -> Result<(), YarerError>
+    where
+        F: NativeClosure + 'static,
+    {
+        if !Session::is_name(name) {
+            return Err(YarerError::InvalidDefinition {
+                name: name.to_string(),
+                span: Span::default(),
+            });
+        }
+        self.insert_function(
+            name.to_lowercase(),
+            Function::Native {
+                arity,
+                fun: Ptr::new(fun),
+            },
+        )
+    }
+
+    /// Compiles an expression in the mode of the session. A name followed by an open bracket
+    /// is a call if it's one of the functions of the session or of `defining`, otherwise
+    /// it's a variable multiplied by the bracket, as in `x (1 + 2)`
+    ///
+    fn compile(&self, expr: &str, defining: &[&str]) -> Result<CompiledExpression, YarerError> {
+        let functions = read(&self.functions);
+        let is_function =
+            |name: &str| functions.contains_key(name) || defining.iter().any(|d| d.eq_ignore_ascii_case(name));
+        CompiledExpression::compile_calling(expr, self.is_implicit(), is_function).map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Inserts the function, updates the inputs of the cells and recomputes the ones calling it.
+    /// If the new inputs make a cycle, the previous function is restored
+    ///
+    fn insert_function(&self, name: String, fun: Function) -> Result<(), YarerError> {
+        {
+            let mut functions = write(&self.functions);
+            let previous = functions.insert(name.clone(), fun);
+            if let Err(e) = self.update_inputs(&functions) {
+                match previous {
+                    Some(previous) => functions.insert(name, previous),
+                    None => functions.remove(&name),
+                };
+                return Err(e);
+            }
+        }
+        self.recompute_callers(&name);
+        Ok(())
+    }
+
+    /// Updates the inputs of all the cells with the variables read by the functions they call.
+    /// The cells are left unchanged if one of them would depend on itself
+    ///
+    fn update_inputs(&self, functions: &HashMap<String, Function>) -> Result<(), YarerError> {
+        let complex = self.is_complex();
+        let mut cells = write(&self.cells);
+        let mut updated = cells.clone();
+        for cell in updated.values_mut() {
+            cell.update_inputs(functions, complex);
+        }
+        for (name, cell) in &updated {
+            if let Some(cycle) = cell::find_cycle(&updated, name, &cell.inputs) {
+                return Err(YarerError::CyclicDefinition {
+                    cycle,
+                    span: Span::default(),
+                });
+            }
+        }
+        *cells = updated;
+        Ok(())
+    }
+
+    /// Returns the [`Function`] defined or registered with the given name, if any
+    ///
+    #[must_use]
+    pub fn function(&self, name: &str) -> Option<Function> {
+        read(&self.functions).get(&name.to_lowercase()).cloned()
+    }
+
+    /// Binds a variable to a formula, like a cell of a spreadsheet: its value is computed right away
+    /// (if all the inputs are defined) and recomputed every time one of its inputs changes,
+    /// with [`Session::set`], [`Session::setf`], an assignment or the formula of another cell,
+    /// and every time a function it calls is redefined. Assigning a value to the variable removes the formula
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.bind("total", "price * qty").unwrap();
+    ///      session.set("price", 3);
+    ///      session.set("qty", 4);
+    ///      assert_eq!(session.process("total").resolve().unwrap().to_string(), "12");
+    ///      session.process("qty = 10").resolve().unwrap();
+    ///      assert_eq!(session.process("total").resolve().unwrap().to_string(), "30");
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// [`YarerError::InvalidDefinition`] if the name is a built-in function or the formula
+    /// assigns a variable, [`YarerError::CyclicDefinition`] if the formula depends on the variable
+    /// itself, directly or through other cells, or the first syntax error of the formula
+    ///
+    pub fn bind(&self, name: &str, formula: &str) -> Result<(), YarerError> {
+        if !Session::is_name(name) {
+            return Err(YarerError::InvalidDefinition {
+                name: name.to_string(),
+                span: Span::default(),
+            });
+        }
+        let name = name.to_lowercase();
+        let complex = self.is_complex();
+        let mut cell = Cell::from_compiled(&name, self.compile(formula, &[])?, complex)?;
+        cell.update_inputs(&read(&self.functions), complex);
+        {
+            let mut cells = write(&self.cells);
+            if let Some(cycle) = cell::find_cycle(&cells, &name, &cell.inputs) {
+                return Err(YarerError::CyclicDefinition {
+                    cycle,
+                    span: Span::default(),
+                });
+            }
+            cells.insert(name.clone(), cell.clone());
+        }
+        {
+            let mut heap = write(&self.variable_heap);
+            match self.resolver(cell.formula.rpn()).resolve_in(&mut heap) {
+                Ok(value) => heap.insert(name.clone(), value),
+                Err(_) => heap.remove(&name),
+            };
+        }
+        self.recompute(&name);
+        Ok(())
+    }
+
+    /// Removes the formula of a cell, keeping its last value.
+    /// Returns false if the variable isn't bound to a formula
+    ///
+    #[must_use]
+    pub fn unbind(&self, name: &str) -> bool {
+        write(&self.cells).remove(&name.to_lowercase()).is_some()
+    }
+
+    /// Returns the [`Cell`] bound to the variable with the given name, if any
+    ///
+    #[must_use]
+    pub fn cell(&self, name: &str) -> Option<Cell> {
+        read(&self.cells).get(&name.to_lowercase()).cloned()
+    }
+
+    /// The cells that depend on the variable, directly or through other cells,
+    /// in the order they are recomputed when the variable changes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let session = Session::init();
+    ///      session.bind("net", "price * qty").unwrap();
+    ///      session.bind("total", "net * (1 + vat)").unwrap();
+    ///      assert_eq!(session.dependents("price"), ["net", "total"]);
+    ///      assert_eq!(session.dependents("vat"), ["total"]);
+    ///  ```
+    ///
+    #[must_use]
+    pub fn dependents(&self, name: &str) -> Vec<String> {
+        cell::dependents(&read(&self.cells), &name.to_lowercase())
+    }
+
+    /// The dependency graph of the cells: the inputs of every cell, by its name
+    ///
+    #[must_use]
+    pub fn dependency_graph(&self) -> BTreeMap<String, BTreeSet<String>> {
+        read(&self.cells)
+            .iter()
+            .map(|(name, cell)| (name.clone(), cell.inputs.clone()))
+            .collect()
+    }
+
+    /// Recomputes the cells that call the function, directly or through the bodies of other
+    /// user functions, after it has been defined or registered
+    ///
+    fn recompute_callers(&self, name: &str) {
+        let calls: Vec<(String, BTreeSet<String>)> = read(&self.functions)
+            .iter()
+            .filter_map(|(caller, fun)| match fun {
+                Function::User(fun) => Some((caller.clone(), fun.body.dependencies().functions)),
+                Function::Native { .. } => None,
+            })
+            .collect();
+        let mut callers = vec![name.to_string()];
+        let mut i = 0;
+        while let Some(callee) = callers.get(i).cloned() {
+            for (caller, callees) in &calls {
+                if callees.contains(&callee) && !callers.contains(caller) {
+                    callers.push(caller.clone());
+                }
+            }
+            i += 1;
+        }
+        for caller in callers {
+            self.recompute(&caller);
+        }
+    }
+
+    /// Recomputes the cells that depend on the variable, after it has changed
+    ///
+    fn recompute(&self, name: &str) {
+        let mut heap = write(&self.variable_heap);
+        cell::recompute(name, &self.cells, &mut heap, |rpn_expr| self.resolver(rpn_expr));
+    }
+
+    /// Returns a copy of all the variables of the session, built-in constants included
+    ///
+    #[must_use]
+    pub fn variables(&self) -> BTreeMap<String, Number> {
+        read(&self.variable_heap)
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Returns all the functions defined or registered in the session
+    ///
+    #[must_use]
+    pub fn functions(&self) -> BTreeMap<String, Function> {
+        read(&self.functions)
+            .iter()
+            .map(|(name, fun)| (name.clone(), fun.clone()))
+            .collect()
+    }
+
+    /// Removes a variable, and its formula if it's a cell. The cells that depend on it are
+    /// recomputed, so they become undefined. Returns false if the variable isn't defined
+    ///
+    #[must_use]
+    pub fn unset(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let removed = write(&self.variable_heap).remove(&name).is_some();
+        write(&self.cells).remove(&name);
+        self.recompute(&name);
+        removed
+    }
+
+    /// Returns a copy of the results resolved by the session so far: the first one is `$1`
+    ///
+    #[must_use]
+    pub fn results(&self) -> Vec<Number> {
+        read(&self.results).clone()
+    }
+
+    /// Returns the n-th result resolved by the session, counting from 1 as `$n` does
+    ///
+    #[must_use]
+    pub fn result(&self, n: usize) -> Option<Number> {
+        read(&self.results).get(n.checked_sub(1)?).cloned()
+    }
+
+    /// Removes all the recorded results, so the next one is `$1` again. `ans` is kept
+    ///
+    pub fn clear_results(&self) {
+        write(&self.results).clear();
+    }
+
+    /// Removes all the variables, functions, cells and results, and restores the built-in constants.
+    /// The settings are kept
+    ///
+    pub fn reset(&self) {
+        let constants = self.constants();
+        *write(&self.variable_heap) = constants;
+        write(&self.functions).clear();
+        write(&self.cells).clear();
+        write(&self.results).clear();
+    }
+
+    /// Saves the variables, the user functions and the cells of the session to a text file,
+    /// one per line, as they would be typed in the REPL: `x = 42`, `hyp(a, b) = sqrt(a^2 + b^2)`,
+    /// `total := price * qty`. Decimal numbers are written as exact fractions (`y = 1/3`),
+    /// so they are restored without any loss of precision.
+    ///
+    /// The built-in constants are saved only if they have been changed, while `ans`,
+    /// the results and the native functions can't be saved at all
+    ///
+    /// # Errors
+    ///
+    /// any I/O error while writing the file
+    ///
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let constants = self.constants();
+        let cells = read(&self.cells);
+        let heap = read(&self.variable_heap);
+        let variables: BTreeMap<&String, &Number> = heap
+            .iter()
+            .filter(|(name, value)| {
+                !cells.contains_key(*name) && constants.get(*name) != Some(value) && *name != ANS
+            })
+            .collect();
+        let functions = read(&self.functions);
+        let functions: BTreeMap<&String, &Function> = functions.iter().collect();
+
+        let mut text = format!("{SESSION_HEADER}\n");
+        for (name, value) in variables {
+            let _ = writeln!(text, "{name} = {}", value.to_exact_string());
+        }
+        for (name, fun) in functions {
+            if let Function::User(fun) = fun {
+                let _ = writeln!(text, "{name}({}) = {}", fun.params.join(", "), fun.body);
+            }
+        }
+        for (name, cell) in cells.iter() {
+            let _ = writeln!(text, "{name} := {}", cell.formula.source().trim());
+        }
+        fs::write(path, text)
+    }
+
+    /// Loads the variables, the user functions and the cells saved by [`Session::save`]
+    /// into the session, replacing the ones with the same names. Blank lines and
+    /// the lines starting with '#' are ignored
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #    use yarer::session::Session;
+    ///
+    ///      let path = std::env::temp_dir().join("yarer_doc_session.txt");
+    ///      let session = Session::init();
+    ///      session.process("x = 1/3").resolve().unwrap();
+    ///      session.save(&path).unwrap();
+    ///
+    ///      let restored = Session::init();
+    ///      restored.load(&path).unwrap();
+    ///      assert_eq!(restored.process("x * 3").resolve().unwrap().to_string(), "1");
+    ///  ```
+    ///
+    /// # Errors
+    ///
+    /// any I/O error while reading the file, or an [`io::ErrorKind::InvalidData`] error
+    /// with the number of the first line that can't be loaded
+    ///
+    pub fn load(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        // the functions can call each other, whatever their order in the file
+        let defining: Vec<&str> = text
+            .lines()
+            .filter_map(|line| Some(Parser::parse_definition(line)?.0))
+            .collect();
+        for (i, line) in text.lines().enumerate() {
+            let invalid = |message: String| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {message}", i + 1))
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, formula)) = Parser::parse_binding(line) {
+                self.bind(name, formula).map_err(|e| invalid(e.to_string()))?;
+            } else if let Some((name, params, body)) = Parser::parse_definition(line) {
+                self.define(name, &params, body, &defining)
+                    .map_err(|e| invalid(e.to_string()))?;
+            } else {
+                let value = line
+                    .split_once('=')
+                    .filter(|(name, _)| Session::is_name(name.trim()))
+                    .and_then(|(name, value)| Some((name.trim(), Number::parse_exact(value)?)));
+                let (name, value) = value.ok_or_else(|| invalid(format!("invalid entry '{line}'")))?;
+                self.assign(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if `name` can name a variable or a function (i.e. it's not a built-in function)
+    ///
+    fn is_name(name: &str) -> bool {
+        matches!(Parser::parse(name).as_deref(), Ok([Token::Variable(_)]))
     }
 
     /// Creates a Variables heap (name-value)
@@ -63,7 +845,7 @@ impl Session {
         local_heap.insert(
             "phi".to_string(),
             Number::DecimalNumber(
-                num_rational::BigRational::from_float((1.0 + 5.0f64.sqrt()) / 2.0).unwrap(),
+                num_rational::BigRational::from_float(f64::midpoint(1.0, 5.0f64.sqrt())).unwrap(),
             ),
         );
         local_heap.insert(
@@ -75,6 +857,44 @@ impl Session {
         local_heap
     }
 
+    /// The built-in constants, with the precision of the session
+    ///
+    fn constants(&self) -> HashMap<String, Number> {
+        let mut constants = match self.precision() {
+            Some(digits) => Session::precise_constants(digits),
+            None => Session::init_local_heap(),
+        };
+        if self.is_complex() {
+            constants.insert(IMAGINARY_UNIT.to_string(), Session::imaginary_unit());
+        }
+        constants
+    }
+
+    fn imaginary_unit() -> Number {
+        Number::ComplexNumber(Complex::new(BigRational::zero(), BigRational::one()))
+    }
+
+    /// Creates the built-in constants with `digits` significant digits
+    ///
+    fn precise_constants(digits: u64) -> HashMap<String, Number> {
+        let math = BigMath::new(digits);
+        let pi = math.pi();
+        let tau = pi.double();
+        [
+            ("pi", pi),
+            ("e", math.e()),
+            ("tau", tau),
+            ("phi", math.phi()),
+            ("gamma", math.gamma()),
+        ]
+        .into_iter()
+        .map(|(name, value)| {
+            let value: BigRational = math.to_rational(&value);
+            (name.to_string(), Number::DecimalNumber(value))
+        })
+        .collect()
+    }
+
     /// Declares and saves a new integer variable ([`Number::NaturalNumber`])
     ///
     /// Example
@@ -83,26 +903,43 @@ impl Session {
     /// ``
     ///
     pub fn set(&self, key: &str, value: i64) {
-        self.variable_heap.borrow_mut().insert(
-            key.to_lowercase(),
-            Number::NaturalNumber(BigInt::from(value)),
-        );
+        self.assign(key, Number::NaturalNumber(BigInt::from(value)));
     }
 
     /// Declares and saves a new float variable ([`Number::DecimalNumber`])
     ///
     /// Example
     /// ``
-    ///     session.setf("x", 1.5);
+    ///     session.setf("x", 1.5)?;
     /// ``
     ///
-    pub fn setf(&self, key: &str, value: f64) {
-        self.variable_heap
-            .borrow_mut()
-            .insert(
-                key.to_lowercase(),
-                Number::DecimalNumber(num_rational::BigRational::from_float(value).unwrap()),
-            );
+    /// # Errors
+    ///
+    /// [`YarerError::Overflow`] if the value is infinite, or [`YarerError::DomainError`]
+    /// if it's NaN: neither can be represented as a rational. The variable is left unchanged
+    ///
+    pub fn setf(&self, key: &str, value: f64) -> Result<(), YarerError> {
+        let value = BigRational::from_float(value).ok_or_else(|| {
+            if value.is_infinite() {
+                YarerError::Overflow { span: Span::default() }
+            } else {
+                YarerError::DomainError {
+                    function: "setf".to_string(),
+                    span: Span::default(),
+                }
+            }
+        })?;
+        self.assign(key, Number::DecimalNumber(value));
+        Ok(())
+    }
+
+    /// Saves a variable, replacing the formula of a cell if any, and updates its dependents
+    ///
+    fn assign(&self, key: &str, value: Number) {
+        let key = key.to_lowercase();
+        write(&self.variable_heap).insert(key.clone(), value);
+        write(&self.cells).remove(&key);
+        self.recompute(&key);
     }
 }
 
@@ -118,7 +955,7 @@ mod tests {
         let mut resolver: RpnResolver = session.process("1+2*3/(4-5)");
         assert_eq!(
             resolver.resolve().unwrap(),
-            Number::DecimalNumber(num_rational::BigRational::from_float(-5.0).unwrap())
+            Number::NaturalNumber(BigInt::from(-5))
         );
     }
 
@@ -130,7 +967,7 @@ mod tests {
         let mut resolver: RpnResolver = session.process("x+2*3/(4-5)");
         assert_eq!(
             resolver.resolve().unwrap(),
-            Number::DecimalNumber(num_rational::BigRational::from_float(-2.0).unwrap())
+            Number::NaturalNumber(BigInt::from(-2))
         );
     }
 
@@ -138,12 +975,16 @@ mod tests {
     #[test]
     fn test_session_setf() {
         let session = Session::init();
-        session.setf("x", 4.5);
+        session.setf("x", 4.5).unwrap();
         let mut resolver: RpnResolver = session.process("x+2*3/(4-5)");
         assert_eq!(
             resolver.resolve().unwrap(),
             Number::DecimalNumber(num_rational::BigRational::from_float(-1.5).unwrap())
         );
+
+        assert!(matches!(session.setf("x", f64::INFINITY), Err(YarerError::Overflow { .. })));
+        assert!(matches!(session.setf("x", f64::NAN), Err(YarerError::DomainError { .. })));
+        assert_eq!(session.process("x").resolve().unwrap().to_string(), "4.5");
     }
 
     /// Test for the default variables initialization
@@ -160,6 +1001,25 @@ mod tests {
         );
     }
 
+    /// Test for listing, removing and resetting the variables
+    #[test]
+    fn test_session_unset_reset() {
+        let session = Session::init();
+        session.set("x", 4);
+        session.bind("y", "x * 2").unwrap();
+        session.define_function("f", &["a"], "a + 1").unwrap();
+        assert_eq!(session.variables()["y"], Number::NaturalNumber(BigInt::from(8)));
+
+        assert!(session.unset("x"));
+        assert!(!session.unset("x"));
+        assert!(!session.variables().contains_key("y"));
+
+        session.reset();
+        assert!(session.functions().is_empty());
+        assert!(session.cell("y").is_none());
+        assert_eq!(session.variables().len(), CONSTANTS.len());
+    }
+
     /// Test for the tau variable
     #[test]
     fn test_session_tau() {
@@ -0,0 +1,67 @@
+#[cfg(not(feature = "sync"))]
+mod imp {
+    use std::{
+        cell::{Ref, RefCell, RefMut},
+        rc::Rc,
+    };
+
+    /// A pointer shared between the session and the resolvers
+    ///
+    pub type Ptr<T> = Rc<T>;
+
+    /// A mutable value shared between the session and the resolvers
+    ///
+    pub type Shared<T> = Rc<RefCell<T>>;
+
+    /// Wraps a value into a [`Shared`] pointer
+    ///
+    pub fn shared<T>(value: T) -> Shared<T> {
+        Rc::new(RefCell::new(value))
+    }
+
+    /// Borrows the shared value to read it
+    ///
+    pub fn read<T>(value: &Shared<T>) -> Ref<'_, T> {
+        value.borrow()
+    }
+
+    /// Borrows the shared value to change it
+    ///
+    pub fn write<T>(value: &Shared<T>) -> RefMut<'_, T> {
+        value.borrow_mut()
+    }
+}
+
+#[cfg(feature = "sync")]
+mod imp {
+    use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    /// A pointer shared between the session and the resolvers
+    ///
+    pub type Ptr<T> = Arc<T>;
+
+    /// A mutable value shared between the session and the resolvers
+    ///
+    pub type Shared<T> = Arc<RwLock<T>>;
+
+    /// Wraps a value into a [`Shared`] pointer
+    ///
+    pub fn shared<T>(value: T) -> Shared<T> {
+        Arc::new(RwLock::new(value))
+    }
+
+    /// Locks the shared value to read it. A lock poisoned by a panic is still readable,
+    /// since the values are never left half-updated
+    ///
+    pub fn read<T>(value: &Shared<T>) -> RwLockReadGuard<'_, T> {
+        value.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the shared value to change it
+    ///
+    pub fn write<T>(value: &Shared<T>) -> RwLockWriteGuard<'_, T> {
+        value.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) use imp::{read, shared, write, Ptr, Shared};
@@ -1,22 +1,241 @@
+use crate::bigmath::BigMath;
+use crate::error::{Span, YarerError};
+use crate::format::{join_complex, NumberFormat};
+use num::complex::{Complex, Complex64};
+use crate::function::Arity;
 use num_traits::ToPrimitive;
 use num_rational::BigRational;
-use log::debug;
-use num_bigint::BigInt;
-use num_traits::FromPrimitive;
+use num_bigint::{BigInt, BigUint};
+use num::Integer;
+use num_traits::{One, Signed, Zero};
 use std::{
+    borrow::Cow,
     fmt::Display,
-    ops::{Add, BitXor, Div, Mul, Sub},
+    ops::{Add, Div, Mul, Sub},
 };
 
-/// Enum Type [Number]. Either an BigInt integer [`Number::NaturalNumber`]
-/// or a [`BigRational`] rational number [`Number::DecimalNumber`]
+/// Enum Type [Number]. Either an [`BigInt`] integer [`Number::NaturalNumber`],
+/// a [`BigRational`] rational number [`Number::DecimalNumber`]
+/// or a [`Complex<BigRational>`] complex number [`Number::ComplexNumber`]
 ///
 #[derive(Debug, PartialEq, Clone)]
 pub enum Number {
-    /// an Integer [BigInt]
+    /// an Integer [`BigInt`]
     NaturalNumber(BigInt),
-    /// a Rational number [BigRational]
+    /// a Rational number [`BigRational`]
     DecimalNumber(BigRational),
+    /// a Complex number with rational parts [`Complex<BigRational>`], whose imaginary part isn't 0
+    ComplexNumber(Complex<BigRational>),
+}
+
+impl Number {
+    /// Wraps a [`BigRational`] into a [Number], collapsing it into a
+    /// [`Number::NaturalNumber`] when the denominator is 1 (i.e. '6/3' is 2)
+    ///
+    #[must_use]
+    pub fn from_rational(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::NaturalNumber(r.to_integer())
+        } else {
+            Number::DecimalNumber(r)
+        }
+    }
+
+    /// Wraps a [`Complex<BigRational>`] into a [Number], collapsing it into a real one
+    /// when the imaginary part is 0 (i.e. 'i*i' is -1)
+    ///
+    #[must_use]
+    pub fn from_complex(c: Complex<BigRational>) -> Number {
+        if c.im.is_zero() {
+            Number::from_rational(c.re)
+        } else {
+            Number::ComplexNumber(c)
+        }
+    }
+
+    /// Returns true if the [Number] is a [`Number::ComplexNumber`]
+    ///
+    #[must_use]
+    pub const fn is_complex(&self) -> bool {
+        matches!(self, Number::ComplexNumber(_))
+    }
+
+    /// Formats the [Number] with up to `digits` significant digits, without the f64 conversion of [Display].
+    /// It's meant for the results of a [`crate::session::Session`] in precision mode.
+    ///
+    #[must_use]
+    pub fn to_precise_string(&self, digits: u64) -> String {
+        match self {
+            Number::NaturalNumber(v) => v.to_string(),
+            Number::DecimalNumber(v) => BigMath::new(digits).rounded(v).to_plain_string(),
+            Number::ComplexNumber(c) => {
+                let part = |r: &BigRational| Number::from_rational(r.clone()).to_precise_string(digits);
+                join_complex((!c.re.is_zero()).then(|| part(&c.re)).as_deref(), &part(&c.im))
+            }
+        }
+    }
+
+    /// Raises the [Number] to the power of `rhs`. Natural numbers with non-negative exponents
+    /// are raised exactly, all the other cases go through f64.
+    ///
+    /// Returns [`None`] if the result is not a number (i.e. '(-8)^0.5') or it's too large.
+    ///
+    #[must_use]
+    pub fn checked_pow(self, rhs: Number) -> Option<Number> {
+        match (self, rhs) {
+            (Number::NaturalNumber(b), Number::NaturalNumber(e)) if !e.is_negative() => {
+                if b.magnitude() <= &BigUint::one() {
+                    // 0, 1 and -1 don't grow, whatever the exponent
+                    let odd = e.is_odd() || b.is_zero();
+                    let res = if e.is_zero() { BigInt::one() } else if odd { b } else { b.abs() };
+                    return Some(Number::NaturalNumber(res));
+                }
+                let e = e.to_u32()?;
+                if !pow_within_limit(b.bits(), u64::from(e)) {
+                    return None;
+                }
+                Some(Number::NaturalNumber(b.pow(e)))
+            }
+            (Number::ComplexNumber(z), Number::NaturalNumber(e)) => {
+                let bits = [z.re.numer(), z.re.denom(), z.im.numer(), z.im.denom()]
+                    .iter()
+                    .map(|v| v.bits())
+                    .max()
+                    .unwrap_or_default();
+                let n = e.magnitude().to_u32()?;
+                if !pow_within_limit(bits, u64::from(n)) {
+                    return None;
+                }
+                let res = z.powu(n);
+                let res = if e.is_negative() { Complex::<BigRational>::one() / res } else { res };
+                Some(Number::from_complex(res))
+            }
+            (b, e) if b.is_complex() || e.is_complex() => b.complex_pow(e),
+            (b, e) => {
+                let res = f64::powf(b.into(), e.into());
+                BigRational::from_float(res).map(Number::DecimalNumber)
+            }
+        }
+    }
+
+    /// Raises the [Number] to the power of `rhs` in the complex plane, taking the principal value:
+    /// '(-8)^(1/3)' is '1+1.7320508075688772i'. It's computed with f64 arithmetic.
+    ///
+    /// Returns [`None`] if the result is not finite.
+    ///
+    #[must_use]
+    pub fn complex_pow(self, rhs: Number) -> Option<Number> {
+        if self.is_zero() {
+            return Some(if rhs.is_zero() { Number::NaturalNumber(BigInt::one()) } else { self });
+        }
+        Number::from_complex64(Complex64::from(self).powc(Complex64::from(rhs)))
+    }
+
+    /// Converts a [`Complex64`] into a [Number], or returns [`None`] if a part isn't finite
+    ///
+    #[must_use]
+    pub fn from_complex64(c: Complex64) -> Option<Number> {
+        let re = BigRational::from_float(c.re)?;
+        let im = BigRational::from_float(c.im)?;
+        Some(Number::from_complex(Complex::new(re, im)))
+    }
+
+    /// Formats the [Number] exactly: a [`Number::NaturalNumber`] as an integer ('42'),
+    /// a [`Number::DecimalNumber`] always as a fraction ('1/3', '2/1') and a [`Number::ComplexNumber`]
+    /// as the sum of its exact parts ('1/2-3i'), so it can be read back with [`Number::parse_exact`]
+    /// without any loss of precision
+    ///
+    #[must_use]
+    pub fn to_exact_string(&self) -> String {
+        match self {
+            Number::NaturalNumber(v) => v.to_string(),
+            Number::DecimalNumber(v) => format!("{}/{}", v.numer(), v.denom()),
+            Number::ComplexNumber(c) => {
+                let re = Number::from_rational(c.re.clone()).to_exact_string();
+                let im = Number::from_rational(c.im.clone()).to_exact_string();
+                let sign = if c.im.is_negative() { "" } else { "+" };
+                format!("{re}{sign}{im}i")
+            }
+        }
+    }
+
+    /// Parses a [Number] formatted by [`Number::to_exact_string`],
+    /// or returns [`None`] if the text isn't an integer, a fraction or a complex number
+    ///
+    #[must_use]
+    pub fn parse_exact(text: &str) -> Option<Number> {
+        if let Some(parts) = text.trim().strip_suffix('i') {
+            let (at, _) = parts.char_indices().rev().find(|(i, c)| *i > 0 && (*c == '+' || *c == '-'))?;
+            let re = Number::parse_exact(&parts[..at])?;
+            let im = Number::parse_exact(&parts[at..])?;
+            return Some(Number::from_complex(Complex::new(re.into(), im.into())));
+        }
+        match text.split_once('/') {
+            None => text.trim().parse().ok().map(Number::NaturalNumber),
+            Some((numer, denom)) => {
+                let numer: BigInt = numer.trim().parse().ok()?;
+                let denom: BigInt = denom.trim().parse().ok()?;
+                if denom.is_zero() {
+                    return None;
+                }
+                Some(Number::DecimalNumber(BigRational::new(numer, denom)))
+            }
+        }
+    }
+
+    /// Returns true if the [Number] is zero, whatever its variant
+    ///
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::NaturalNumber(v) => v.is_zero(),
+            Number::DecimalNumber(v) => v.is_zero(),
+            Number::ComplexNumber(c) => c.is_zero(),
+        }
+    }
+}
+
+/// The largest number of bits that an exact power can produce before it's considered an overflow
+///
+const POW_BITS_LIMIT: u64 = 1 << 22;
+
+/// Checks if raising a number of `bits` bits to the power of `exponent` produces a reasonable result
+///
+pub(crate) fn pow_within_limit(bits: u64, exponent: u64) -> bool {
+    bits.saturating_mul(exponent) <= POW_BITS_LIMIT
+}
+
+/// Converts a [Number] into an exact [`BigRational`], without any loss of precision.
+/// A [`Number::ComplexNumber`] loses its imaginary part
+///
+impl From<Number> for BigRational {
+    fn from(n: Number) -> BigRational {
+        match n {
+            Number::NaturalNumber(v) => BigRational::from_integer(v),
+            Number::DecimalNumber(v) => v,
+            Number::ComplexNumber(c) => c.re,
+        }
+    }
+}
+
+/// Converts a [Number] into an exact [`Complex<BigRational>`], with a 0 imaginary part if it's real
+///
+impl From<Number> for Complex<BigRational> {
+    fn from(n: Number) -> Complex<BigRational> {
+        match n {
+            Number::ComplexNumber(c) => c,
+            real => Complex::new(real.into(), BigRational::zero()),
+        }
+    }
+}
+
+/// Converts a [Number] into the closest [`Complex64`]
+///
+impl From<Number> for Complex64 {
+    fn from(n: Number) -> Complex64 {
+        let c = Complex::<BigRational>::from(n);
+        Complex64::new(c.re.to_f64().unwrap_or(f64::NAN), c.im.to_f64().unwrap_or(f64::NAN))
+    }
 }
 
 /// A binary or unary Math [`Operator`]
@@ -39,6 +258,33 @@ pub enum Operator {
     Fac,
     /// Binary Assignment ('A=1')
     Eql,
+    /// Equality ('a == b'), 1 if true and 0 if false
+    Eq,
+    /// Inequality ('a != b' or 'a ≠ b')
+    Ne,
+    /// Less than ('a < b')
+    Lt,
+    /// Less than or equal to ('a <= b' or 'a ≤ b')
+    Le,
+    /// Greater than ('a > b')
+    Gt,
+    /// Greater than or equal to ('a >= b' or 'a ≥ b')
+    Ge,
+    /// Logical and ('a && b'): 1 if both the operands aren't 0
+    And,
+    /// Logical or ('a || b'): 1 if any of the operands isn't 0
+    Or,
+    /// Logical not, a prefix operator ('not a'): 1 if the operand is 0
+    Not,
+}
+
+impl Operator {
+    /// Returns true if the [Operator] has a single operand ('-x', 'x!', 'not x')
+    ///
+    #[must_use]
+    pub const fn is_unary(self) -> bool {
+        matches!(self, Operator::Une | Operator::Fac | Operator::Not)
+    }
 }
 
 /// The "associativity" of an operator dictates the direction
@@ -74,6 +320,7 @@ pub enum Bracket {
 /// [`Token::Bracket`] as [] or ()
 /// [`Token::Function`] as sin,cos,tan,ln ...
 /// [`Token::Variable`] as any variable name such as x,y,ab,foo,... whatever
+/// [`Token::UserFunction`] as any other name followed by a bracket, such as hyp(3, 4)
 ///
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'a> {
@@ -83,14 +330,18 @@ pub enum Token<'a> {
     Operator(Operator),
     /// ( ) [ ]
     Bracket(Bracket),
-    /// sin cos tan ln log...
-    Function(MathFunction),
+    /// sin cos tan ln log..., with the number of arguments between the brackets
+    /// (counted while converting to RPN)
+    Function(MathFunction, usize),
     /// comma separator for function arguments
     Comma,
     /// a b c x y ...
-    Variable(&'a str),
+    Variable(Cow<'a, str>),
     /// Semicolon ';' separator for chained expressions
     SemiColon,
+    /// f g hyp ... called as f(x), with the number of arguments between the brackets
+    /// (counted while converting to RPN)
+    UserFunction(Cow<'a, str>, usize),
 }
 
 /// The [`MathFunction`] enum. It represents a common math function.
@@ -121,6 +372,12 @@ pub enum MathFunction {
     Max,
     /// Min value
     Min,
+    /// Sum of the values
+    Sum,
+    /// Arithmetic mean of the values
+    Avg,
+    /// Euclidean norm, the square root of the sum of the squares
+    Hypot,
     /// Rounds down
     Floor,
     /// Rounds up
@@ -144,17 +401,40 @@ impl Token<'_> {
     const fn from_operator(c: char) -> Option<Token<'static>> {
         match c {
             '+' => Some(Token::Operator(Operator::Add)),
-            '-' => Some(Token::Operator(Operator::Sub)),
-            '*' => Some(Token::Operator(Operator::Mul)),
-            '/' => Some(Token::Operator(Operator::Div)),
+            '-' | '−' => Some(Token::Operator(Operator::Sub)),
+            '*' | '×' | '·' => Some(Token::Operator(Operator::Mul)),
+            '/' | '÷' => Some(Token::Operator(Operator::Div)),
             '^' => Some(Token::Operator(Operator::Pow)),
             '#' => Some(Token::Operator(Operator::Une)),
             '!' => Some(Token::Operator(Operator::Fac)),
             '=' => Some(Token::Operator(Operator::Eql)),
+            '≠' => Some(Token::Operator(Operator::Ne)),
+            '<' => Some(Token::Operator(Operator::Lt)),
+            '≤' => Some(Token::Operator(Operator::Le)),
+            '>' => Some(Token::Operator(Operator::Gt)),
+            '≥' => Some(Token::Operator(Operator::Ge)),
             _ => None,
         }
     }
 
+    /// Converts the operators written with two chars ('<=', '&&') or with a word ('not')
+    /// to a [`Token::Operator`], or just returns [`None`] if nothing matches.
+    /// The word is case-sensitive, so 'NOT' or 'Not' are variables like any other name
+    ///
+    fn from_long_operator(t: &str) -> Option<Token<'static>> {
+        let op = match t {
+            "==" => Operator::Eq,
+            "!=" => Operator::Ne,
+            "<=" => Operator::Le,
+            ">=" => Operator::Ge,
+            "&&" => Operator::And,
+            "||" => Operator::Or,
+            "not" => Operator::Not,
+            _ => return None,
+        };
+        Some(Token::Operator(op))
+    }
+
     /// Converts a char to a [`Token::Bracket`]
     /// or just returns [`None`] if nothing matches.
     ///
@@ -169,7 +449,7 @@ impl Token<'_> {
     /// Converts a &str to a [`Token::Function(MathFunction)`]
     /// or just returns [`None`] if nothing matches.
     ///
-    fn get_some(fun: &str) -> Option<MathFunction> {
+    pub(crate) fn get_some(fun: &str) -> Option<MathFunction> {
         match fun.to_lowercase().as_str() {
             "sin" => Some(MathFunction::Sin),
             "cos" => Some(MathFunction::Cos),
@@ -183,6 +463,9 @@ impl Token<'_> {
             "sqrt" => Some(MathFunction::Sqrt),
             "max" => Some(MathFunction::Max),
             "min" => Some(MathFunction::Min),
+            "sum" => Some(MathFunction::Sum),
+            "avg" => Some(MathFunction::Avg),
+            "hypot" => Some(MathFunction::Hypot),
             "floor" => Some(MathFunction::Floor),
             "ceil" => Some(MathFunction::Ceil),
             "round" => Some(MathFunction::Round),
@@ -199,17 +482,33 @@ impl Token<'_> {
     /// "("   -> [`Token::Bracket`]
     /// "42"  -> [`Token::Operand(Token::NaturalNumber)`]
     /// "6.6" -> [`Token::Operand(Token::DecimalNumber)`]
+    /// "1e-9" -> [`Token::Operand(Token::DecimalNumber)`]
+    /// "0xFF" -> [`Token::Operand(Token::NaturalNumber)`]
     /// "sin" -> [`Token::Function`]
     /// "x"   -> [`Token::Variable`]
+    /// "×"   -> [`Token::Operator`], as "*"
+    /// "π"   -> [`Token::Variable`], as "pi"
+    /// "<="  -> [`Token::Operator`]
+    /// "not" -> [`Token::Operator`]
+    ///
+    /// A number too large to be represented ('1e99999999') is [`None`]
     ///
     #[must_use]
-    pub fn tokenize(t: &str) -> Option<Token> {
+    pub fn tokenize(t: &str) -> Option<Token<'_>> {
+        if let Some(op) = Token::from_long_operator(t) {
+            return Some(op);
+        }
         match t.chars().next() {
             Some(s) => match s {
-                c @ ('+' | '-' | '*' | '/' | '^' | '!' | '=') => {
-                    return Some(Token::from_operator(c).unwrap())
+                c @ ('+' | '-' | '*' | '/' | '^' | '!' | '=' | '×' | '·' | '÷' | '−' | '≠' | '<' | '≤' | '>' | '≥') => {
+                    return Token::from_operator(c)
                 }
-                b @ ('(' | ')' | '[' | ']') => return Some(Token::from_bracket(b).unwrap()),
+                b @ ('(' | ')' | '[' | ']') => return Token::from_bracket(b),
+                // a prefix square root, as 'sqrt' without the brackets
+                '√' => return Some(Token::Function(MathFunction::Sqrt, 1)),
+                'π' => return Some(Token::Variable(Cow::Borrowed("pi"))),
+                'τ' => return Some(Token::Variable(Cow::Borrowed("tau"))),
+                'φ' => return Some(Token::Variable(Cow::Borrowed("phi"))),
                 ',' => return Some(Token::Comma),
                 ';' => return Some(Token::SemiColon),
                 _ => (), // continue the flow
@@ -217,33 +516,116 @@ impl Token<'_> {
             None => return None,
         }
 
-        if let Ok(v) = t.parse::<BigInt>() {
-            return Some(Token::Operand(Number::NaturalNumber(v)));
+        if let Some(n) = Token::parse_literal(t) {
+            return Some(Token::Operand(n));
         }
-
-        if let Ok(v) = t.parse::<f64>() {
-            if let Some(r) = BigRational::from_float(v) {
-                return Some(Token::Operand(Number::DecimalNumber(r)));
-            }
+        // a number too large to be represented
+        if t.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return None;
         }
 
         if let Some(fun) = Token::get_some(t) {
-            return Some(Token::Function(fun));
+            return Some(Token::Function(fun, 1));
+        }
+
+        Some(Token::Variable(Cow::Borrowed(t)))
+    }
+
+    /// Parses a number literal: an integer (`1_000_000`), a hex, binary or octal integer
+    /// ('0xFF', '0b1010', '0o17') or a decimal number with an optional exponent ('2.5E+3').
+    /// Both are exact: '0.1' is 1/10, not the f64 closest to it
+    ///
+    fn parse_literal(t: &str) -> Option<Number> {
+        if !t.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let digits = t.replace('_', "");
+        let radix = match digits.get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0b" | "0B") => 2,
+            Some("0o" | "0O") => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            return BigInt::parse_bytes(&digits.as_bytes()[2..], radix).map(Number::NaturalNumber);
+        }
+        if let Ok(v) = digits.parse::<BigInt>() {
+            return Some(Number::NaturalNumber(v));
+        }
+        Token::parse_decimal(&digits).map(Number::DecimalNumber)
+    }
+
+    /// Parses a decimal number with an optional exponent ('2.5e3') into an exact [`BigRational`],
+    /// or returns [`None`] if the exponent is too large
+    ///
+    fn parse_decimal(digits: &str) -> Option<BigRational> {
+        let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+            None => (digits, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let n: BigInt = format!("{int}{frac}").parse().ok()?;
+        let scale = exponent.checked_sub(i64::try_from(frac.len()).ok()?)?;
+        // a power of 10 takes less than 4 bits per digit
+        if !pow_within_limit(4, scale.unsigned_abs()) {
+            return None;
+        }
+        let power = BigInt::from(10).pow(u32::try_from(scale.unsigned_abs()).ok()?);
+        Some(if scale < 0 {
+            BigRational::new(n, power)
+        } else {
+            BigRational::from_integer(n * power)
+        })
+    }
+
+    /// Converts a [Token] into one that owns its names, so it doesn't borrow the expression anymore
+    ///
+    #[must_use]
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Operand(v) => Token::Operand(v),
+            Token::Operator(v) => Token::Operator(v),
+            Token::Bracket(v) => Token::Bracket(v),
+            Token::Function(v, n) => Token::Function(v, n),
+            Token::Comma => Token::Comma,
+            Token::Variable(v) => Token::Variable(Cow::Owned(v.into_owned())),
+            Token::SemiColon => Token::SemiColon,
+            Token::UserFunction(v, n) => Token::UserFunction(Cow::Owned(v.into_owned()), n),
         }
+    }
 
-        Some(Token::Variable(t))
+    /// The textual form of a [Token], as it's written in an expression
+    ///
+    #[must_use]
+    pub fn symbol(&self) -> String {
+        match self {
+            Token::Operand(v) => v.to_string(),
+            Token::Operator(Operator::Une) => "-".to_string(),
+            Token::Operator(v) => v.to_string(),
+            Token::Bracket(v) => v.to_string(),
+            Token::Function(v, _) => v.to_string().to_lowercase(),
+            Token::Variable(v) | Token::UserFunction(v, _) => v.to_string(),
+            Token::Comma => ",".to_string(),
+            Token::SemiColon => ";".to_string(),
+        }
     }
 
     /// Founding out the priority and the associative precedence of an operator
     ///
-    fn operator_priority(o: Token) -> (u8, Associate) {
+    pub(crate) fn operator_priority(o: &Token) -> (u8, Associate) {
         match o {
-            Token::Operator(Operator::Add | Operator::Sub) => (1, Associate::LeftAssociative),
-            Token::Operator(Operator::Mul | Operator::Div) => (2, Associate::LeftAssociative),
-            Token::Operator(Operator::Pow) => (3, Associate::RightAssociative),
-            Token::Operator(Operator::Une) => (4, Associate::RightAssociative),
-            Token::Operator(Operator::Fac) => (5, Associate::LeftAssociative),
             Token::Operator(Operator::Eql) => (0, Associate::RightAssociative),
+            Token::Operator(Operator::Or) => (1, Associate::LeftAssociative),
+            Token::Operator(Operator::And) => (2, Associate::LeftAssociative),
+            Token::Operator(Operator::Not) => (3, Associate::RightAssociative),
+            Token::Operator(
+                Operator::Eq | Operator::Ne | Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge,
+            ) => (4, Associate::LeftAssociative),
+            Token::Operator(Operator::Add | Operator::Sub) => (5, Associate::LeftAssociative),
+            Token::Operator(Operator::Mul | Operator::Div) => (6, Associate::LeftAssociative),
+            Token::Operator(Operator::Pow) => (7, Associate::RightAssociative),
+            Token::Operator(Operator::Une) => (8, Associate::RightAssociative),
+            Token::Operator(Operator::Fac) => (9, Associate::LeftAssociative),
             _ => panic!("Operator '{o}' not recognised. This must not happen!"),
         }
     }
@@ -251,12 +633,12 @@ impl Token<'_> {
     /// Checks if an operator has priority over another one
     ///
     /// i.e.
-    /// * has priority over +
-    /// ^ has priority over *
-    /// unary - has priority over ^
+    /// * has priority over +,
+    ///   ^ has priority over *,
+    ///   unary - has priority over ^
     ///
     #[must_use]
-    pub fn compare_operator_priority(op1: Token, op2: Token) -> bool {
+    pub fn compare_operator_priority(op1: &Token, op2: &Token) -> bool {
         let v_op1: (u8, Associate) = self::Token::operator_priority(op1);
         let v_op2: (u8, Associate) = self::Token::operator_priority(op2);
 
@@ -265,37 +647,34 @@ impl Token<'_> {
     }
 }
 
-/// Let's display a [`Number::NaturalNumber`] or a [`Number::DecimalNumber`] properly
+/// Let's display a [`Number::NaturalNumber`] or a [`Number::DecimalNumber`] properly,
+/// with the default [`NumberFormat`]
 ///
 impl Display for Number {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Number::NaturalNumber(v) => write!(f, "{v}"),
-            Number::DecimalNumber(v) => {
-                let fl = v.to_f64().expect("Should not happen");
-                write!(f, "{fl}")
-            }
-        }
+        write!(f, "{}", self.format(&NumberFormat::default()))
     }
 }
 
-/// The main operational functional closure. It handles 4 different cases:
+/// The main operational functional closure. It handles 5 different cases:
 ///
 /// 1. Natural (op) Natural returns Natural
 /// 2. Natural (op) Decimal returns Decimal
 /// 3. Decimal (op) Decimal returns Decimal
 /// 4. Decimal (op) Natural returns Decimal
+/// 5. Complex (op) any, or any (op) Complex, returns Complex (or real, if the imaginary part is 0)
 ///
-/// (op) can be [Add], [Mul], [Sub], [Div], [BitXor], ...
+/// (op) can be [Add], [Mul], [Sub], ...
 ///
-/// We define 2 closures: 1 specialised for Natural Numbers and the other one specialised for Decimals.
+/// We define 3 closures: specialised for Natural Numbers, for Decimals and for Complex numbers.
 ///
-fn apply_functional_token_operation<NF, DF>(ln: Number, rn: Number, nf: NF, df: DF) -> Number
+fn apply_functional_token_operation<NF, DF, CF>(ln: Number, rn: Number, nf: NF, df: DF, cf: CF) -> Number
 where
     NF: Fn(BigInt, BigInt) -> BigInt,
     DF: Fn(BigRational, BigRational) -> BigRational,
+    CF: Fn(Complex<BigRational>, Complex<BigRational>) -> Complex<BigRational>,
 {
-    match (ln, rn.clone()) {
+    match (ln, rn) {
         (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => Number::NaturalNumber(nf(v1, v2)),
         (Number::NaturalNumber(v1), Number::DecimalNumber(v2)) => {
             Number::DecimalNumber(df(BigRational::from(v1), v2))
@@ -304,6 +683,7 @@ where
             Number::DecimalNumber(df(v1, BigRational::from(v2)))
         }
         (Number::DecimalNumber(v1), Number::DecimalNumber(v2)) => Number::DecimalNumber(df(v1, v2)),
+        (l, r) => Number::from_complex(cf(l.into(), r.into())),
     }
 }
 
@@ -311,7 +691,7 @@ impl Add for Number {
     type Output = Number;
 
     fn add(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a + b, |a, b| a + b)
+        apply_functional_token_operation(self, rhs, |a, b| a + b, |a, b| a + b, |a, b| a + b)
     }
 }
 
@@ -319,7 +699,7 @@ impl Sub for Number {
     type Output = Number;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a - b, |a, b| a - b)
+        apply_functional_token_operation(self, rhs, |a, b| a - b, |a, b| a - b, |a, b| a - b)
     }
 }
 
@@ -327,98 +707,117 @@ impl Mul for Number {
     type Output = Number;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a * b, |a, b| a * b)
+        apply_functional_token_operation(self, rhs, |a, b| a * b, |a, b| a * b, |a, b| a * b)
     }
 }
 
+/// The division is always exact: both operands are promoted to [`BigRational`]
+/// and the quotient goes back to a [`Number::NaturalNumber`] if it divides evenly.
+///
+/// # Panics
+///
+/// if `rhs` is zero. The [`crate::rpn_resolver::RpnResolver`] checks it before dividing.
+///
 impl Div for Number {
     type Output = Number;
 
     fn div(self, rhs: Self) -> Self::Output {
-        apply_functional_token_operation(self, rhs, |a, b| a / b, |a, b| a / b)
-    }
-}
-
-impl BitXor for Number {
-    type Output = Number;
-
-    fn bitxor(self, rhs: Self) -> Self::Output {
-        debug!("{} {}", self, rhs);
-        apply_functional_token_operation(
-            self,
-            rhs,
-            |a, b| BigInt::pow(&a, b.try_into().unwrap()),
-            |a, b| {
-                let af = a.to_f64().expect("Should not happen");
-                let bf = b.to_f64().expect("Should not happen");
-                BigRational::from_float(f64::powf(af, bf)).expect("Should not happen")
-            },
-        )
+        if self.is_complex() || rhs.is_complex() {
+            return Number::from_complex(Complex::<BigRational>::from(self) / Complex::<BigRational>::from(rhs));
+        }
+        Number::from_rational(BigRational::from(self) / BigRational::from(rhs))
     }
 }
 
-/// PartialOrd between [Number]s with the required conversions.
+/// `PartialOrd` between [Number]s with the required conversions.
+/// The complex numbers aren't ordered: comparing them returns [`None`]
 ///
 impl PartialOrd for Number {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
-            (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => v1.partial_cmp(&v2),
+            (Number::ComplexNumber(_), _) | (_, Number::ComplexNumber(_)) => None,
+            (Number::NaturalNumber(v1), Number::NaturalNumber(v2)) => v1.partial_cmp(v2),
             (Number::NaturalNumber(v1), Number::DecimalNumber(v2)) => {
                 BigRational::from(v1.clone()).partial_cmp(v2)
             }
             (Number::DecimalNumber(v1), Number::NaturalNumber(v2)) => {
                 v1.partial_cmp(&BigRational::from(v2.clone()))
             }
-            (Number::DecimalNumber(v1), Number::DecimalNumber(v2)) => v1.partial_cmp(&v2),
+            (Number::DecimalNumber(v1), Number::DecimalNumber(v2)) => v1.partial_cmp(v2),
         }
     }
 }
 
+/// Converts a bool into the [`Number::NaturalNumber`] 1 if it's true, or 0 if it's false.
+/// It's how the comparisons and the logical operators return their results
+///
+impl From<bool> for Number {
+    fn from(b: bool) -> Number {
+        Number::NaturalNumber(BigInt::from(u8::from(b)))
+    }
+}
+
+/// Converts a [Number] into the closest f64, or an infinity if it's too large.
+/// A [`Number::ComplexNumber`] is NaN
+///
 impl From<Number> for f64 {
     fn from(n: Number) -> f64 {
         match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_f64(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => v.to_f64().expect("Should not happen"),
+            Number::NaturalNumber(v) => ToPrimitive::to_f64(&v).unwrap_or(f64::NAN),
+            Number::DecimalNumber(v) => v.to_f64().unwrap_or(f64::NAN),
+            Number::ComplexNumber(_) => f64::NAN,
         }
     }
 }
 
-#[allow(clippy::cast_possible_truncation)]
-impl From<Number> for BigInt {
-    fn from(n: Number) -> BigInt {
+/// Converts a [Number] into an integer, truncating its decimals.
+/// A [`Number::ComplexNumber`] isn't an integer: it's a [`YarerError::DomainError`]
+///
+impl TryFrom<Number> for BigInt {
+    type Error = YarerError;
+
+    fn try_from(n: Number) -> Result<BigInt, YarerError> {
         match n {
-            Number::NaturalNumber(v) => v,
-            Number::DecimalNumber(v) => {
-                BigInt::from_f64(v.to_f64().expect("Should not happen")).expect("Should not happen")
-            }
+            Number::NaturalNumber(v) => Ok(v),
+            Number::DecimalNumber(v) => Ok(v.to_integer()),
+            Number::ComplexNumber(_) => Err(YarerError::DomainError {
+                function: "integer".to_string(),
+                span: Span::default(),
+            }),
         }
     }
 }
 
-impl From<Number> for i32 {
-    fn from(n: Number) -> i32 {
-        match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i32(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i32(&BigInt::from_f64(v.to_f64().expect("Should not happen")).expect("Should not happen")).expect("Should not happen"),
-        }
+/// Converts a [Number] into an i32, truncating its decimals.
+/// It's a [`YarerError::Overflow`] if it's out of the range of an i32
+///
+impl TryFrom<Number> for i32 {
+    type Error = YarerError;
+
+    fn try_from(n: Number) -> Result<i32, YarerError> {
+        BigInt::try_from(n)?.to_i32().ok_or(YarerError::Overflow { span: Span::default() })
     }
 }
 
-impl From<Number> for i64 {
-    fn from(n: Number) -> i64 {
-        match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i64(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i64(&BigInt::from_f64(v.to_f64().expect("Should not happen")).expect("Should not happen")).expect("Should not happen"),
-        }
+/// Converts a [Number] into an i64, truncating its decimals.
+/// It's a [`YarerError::Overflow`] if it's out of the range of an i64
+///
+impl TryFrom<Number> for i64 {
+    type Error = YarerError;
+
+    fn try_from(n: Number) -> Result<i64, YarerError> {
+        BigInt::try_from(n)?.to_i64().ok_or(YarerError::Overflow { span: Span::default() })
     }
 }
 
-impl From<Number> for i128 {
-    fn from(n: Number) -> i128 {
-        match n {
-            Number::NaturalNumber(v) => ToPrimitive::to_i128(&v).expect("Should not happen"),
-            Number::DecimalNumber(v) => ToPrimitive::to_i128(&BigInt::from_f64(v.to_f64().expect("Should not happen")).expect("Should not happen")).expect("Should not happen"),
-        }
+/// Converts a [Number] into an i128, truncating its decimals.
+/// It's a [`YarerError::Overflow`] if it's out of the range of an i128
+///
+impl TryFrom<Number> for i128 {
+    type Error = YarerError;
+
+    fn try_from(n: Number) -> Result<i128, YarerError> {
+        BigInt::try_from(n)?.to_i128().ok_or(YarerError::Overflow { span: Span::default() })
     }
 }
 
@@ -433,6 +832,15 @@ impl Display for Operator {
             Operator::Une => write!(f, "#"),
             Operator::Fac => write!(f, "!"),
             Operator::Eql => write!(f, "="),
+            Operator::Eq => write!(f, "=="),
+            Operator::Ne => write!(f, "!="),
+            Operator::Lt => write!(f, "<"),
+            Operator::Le => write!(f, "<="),
+            Operator::Gt => write!(f, ">"),
+            Operator::Ge => write!(f, ">="),
+            Operator::And => write!(f, "&&"),
+            Operator::Or => write!(f, "||"),
+            Operator::Not => write!(f, "not"),
         }
     }
 }
@@ -446,6 +854,86 @@ impl Display for Bracket {
     }
 }
 
+impl MathFunction {
+    /// All the built-in functions
+    ///
+    pub const ALL: [MathFunction; 21] = [
+        MathFunction::Sin,
+        MathFunction::Cos,
+        MathFunction::Tan,
+        MathFunction::ASin,
+        MathFunction::ACos,
+        MathFunction::ATan,
+        MathFunction::Ln,
+        MathFunction::Log,
+        MathFunction::Abs,
+        MathFunction::Sqrt,
+        MathFunction::Max,
+        MathFunction::Min,
+        MathFunction::Sum,
+        MathFunction::Avg,
+        MathFunction::Hypot,
+        MathFunction::Floor,
+        MathFunction::Ceil,
+        MathFunction::Round,
+        MathFunction::Exp,
+        MathFunction::Pdf,
+        MathFunction::Cdf,
+    ];
+
+    /// The name of the function, as it's written in an expression ('sin', 'asin')
+    ///
+    #[must_use]
+    pub fn name(self) -> String {
+        self.to_string().to_lowercase()
+    }
+
+    /// A short description of what the function computes
+    ///
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            MathFunction::Sin => "Trigonometric sine, in radians",
+            MathFunction::Cos => "Trigonometric cosine, in radians",
+            MathFunction::Tan => "Trigonometric tangent, in radians",
+            MathFunction::ASin => "Arcsine, in radians",
+            MathFunction::ACos => "Arccosine, in radians",
+            MathFunction::ATan => "Arctangent, in radians",
+            MathFunction::Ln => "Natural logarithm",
+            MathFunction::Log => "Base 10 logarithm",
+            MathFunction::Abs => "Absolute value",
+            MathFunction::Sqrt => "Square root",
+            MathFunction::Max => "The largest of the values",
+            MathFunction::Min => "The smallest of the values",
+            MathFunction::Sum => "Sum of the values",
+            MathFunction::Avg => "Arithmetic mean of the values",
+            MathFunction::Hypot => "Euclidean norm, the square root of the sum of the squares",
+            MathFunction::Floor => "Rounds down",
+            MathFunction::Ceil => "Rounds up",
+            MathFunction::Round => "Rounds to the nearest integer",
+            MathFunction::Exp => "e^x exponentiation",
+            MathFunction::Pdf => "Standard normal probability density function",
+            MathFunction::Cdf => "Standard normal cumulative distribution function",
+            MathFunction::None => "No function",
+        }
+    }
+
+    /// The number of arguments accepted by the function: max, min, sum, avg and hypot
+    /// are variadic, all the others take exactly one argument
+    ///
+    #[must_use]
+    pub const fn arity(self) -> Arity {
+        match self {
+            MathFunction::Max
+            | MathFunction::Min
+            | MathFunction::Sum
+            | MathFunction::Avg
+            | MathFunction::Hypot => Arity::AtLeast(1),
+            _ => Arity::Exact(1),
+        }
+    }
+}
+
 impl Display for MathFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", *self)
@@ -458,8 +946,9 @@ impl Display for Token<'_> {
             Token::Operand(v) => write!(f, "({v})"),
             Token::Operator(v) => write!(f, "({v})"),
             Token::Bracket(v) => write!(f, "({v})"),
-            Token::Function(v) => write!(f, "({v})"),
+            Token::Function(v, n) => write!(f, "({v}/{n})"),
             Token::Variable(v) => write!(f, "({v})"),
+            Token::UserFunction(v, n) => write!(f, "({v}/{n})"),
             Token::Comma => write!(f, "(,)") ,
             Token::SemiColon => write!(f, "(;)")
         }
@@ -472,9 +961,36 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_exact_string() {
+        let third = Number::DecimalNumber(BigRational::new(BigInt::from(-1), BigInt::from(3)));
+        let two = Number::DecimalNumber(BigRational::from_integer(BigInt::from(2)));
+        let big = Number::NaturalNumber(BigInt::from(7).pow(100));
+        for n in [third, two, big] {
+            assert_eq!(Number::parse_exact(&n.to_exact_string()), Some(n));
+        }
+        assert_eq!(Number::parse_exact("2/1").unwrap().to_exact_string(), "2/1");
+        assert_eq!(Number::parse_exact("1/0"), None);
+        assert_eq!(Number::parse_exact("1.5"), None);
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        let decimal = |n: i64, d: i64| Number::DecimalNumber(BigRational::new(BigInt::from(n), BigInt::from(d)));
+        assert_eq!(i32::try_from(decimal(-7, 2)), Ok(-3));
+        assert_eq!(i64::try_from(Number::NaturalNumber(BigInt::from(i64::MAX))), Ok(i64::MAX));
+        let huge_decimal = Number::DecimalNumber(BigRational::new(BigInt::from(10).pow(401) + 5, BigInt::from(10)));
+        assert_eq!(BigInt::try_from(huge_decimal), Ok(BigInt::from(10).pow(400)));
+        let huge = Number::NaturalNumber(BigInt::from(2).pow(100));
+        assert_eq!(i32::try_from(huge.clone()), Err(YarerError::Overflow { span: Span::default() }));
+        assert!(i128::try_from(huge * Number::NaturalNumber(BigInt::from(2).pow(100))).is_err());
+        let i = Number::from_complex(Complex::new(BigRational::zero(), BigRational::one()));
+        assert!(matches!(i64::try_from(i), Err(YarerError::DomainError { .. })));
+    }
+
     #[test]
     fn test_tokenise_operators() {
-        let v = vec!["1", "+", "2.1"];
+        let v = ["1", "+", "2.1"];
         assert_eq!(Token::tokenize(v[1]), Some(Token::Operator(Operator::Add)));
         assert_eq!(
             Token::tokenize(v[0]),
@@ -483,7 +999,7 @@ mod tests {
         assert_eq!(
             Token::tokenize(v[2]),
             Some(Token::Operand(Number::DecimalNumber(
-                BigRational::from_float(2.1).unwrap()
+                BigRational::new(BigInt::from(21), BigInt::from(10))
             )))
         );
     }
@@ -529,12 +1045,37 @@ mod tests {
         assert_eq!(
             Token::tokenize("3.14"),
             Some(Token::Operand(Number::DecimalNumber(
-                BigRational::from_float(3.14).unwrap()
+                BigRational::new(BigInt::from(314), BigInt::from(100))
             )))
         );
         assert_eq!(Token::tokenize("("), Some(Token::Bracket(Bracket::Open)));
     }
 
+    #[test]
+    fn test_tokenize_literals() {
+        let natural = |t| match Token::tokenize(t) {
+            Some(Token::Operand(Number::NaturalNumber(n))) => n,
+            other => panic!("{t} is {other:?}"),
+        };
+        assert_eq!(natural("0xff_ff"), BigInt::from(0xffff));
+        assert_eq!(natural("0B1010"), BigInt::from(10));
+        assert_eq!(natural("0o17"), BigInt::from(15));
+        assert_eq!(natural("1_000_000"), BigInt::from(1_000_000));
+        assert_eq!(natural("0xFFFFFFFFFFFFFFFFFFFF"), BigInt::from(2).pow(80) - 1);
+
+        let decimal = |t| match Token::tokenize(t) {
+            Some(Token::Operand(Number::DecimalNumber(r))) => r,
+            other => panic!("{t} is {other:?}"),
+        };
+        assert_eq!(decimal("2.5E+3"), BigRational::from_float(2500.0).unwrap());
+        assert_eq!(decimal("1e-9"), BigRational::new(BigInt::from(1), BigInt::from(10).pow(9)));
+        assert_eq!(decimal("1e-9"), decimal("0.000_000_001"));
+        assert_eq!(decimal("0.1"), BigRational::new(BigInt::from(1), BigInt::from(10)));
+        // out of the f64 range
+        assert_eq!(decimal("1e400"), BigRational::from_integer(BigInt::from(10).pow(400)));
+        assert_eq!(decimal("2.5e-400"), BigRational::new(BigInt::from(25), BigInt::from(10).pow(401)));
+    }
+
     #[test]
     fn test_tokenize_vec_valid() {
         assert_eq!(Token::tokenize("+"), Some(Token::Operator(Operator::Add)));
@@ -545,7 +1086,7 @@ mod tests {
         assert_eq!(
             Token::tokenize("3.14"),
             Some(Token::Operand(Number::DecimalNumber(
-                BigRational::from_float(3.14).unwrap()
+                BigRational::new(BigInt::from(314), BigInt::from(100))
             )))
         );
         assert_eq!(Token::tokenize("("), Some(Token::Bracket(Bracket::Open)));
@@ -554,32 +1095,58 @@ mod tests {
     #[test]
     fn test_operator_priority() {
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Add)),
-            (1, Associate::LeftAssociative)
+            Token::operator_priority(&Token::Operator(Operator::Add)),
+            (5, Associate::LeftAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Sub)),
-            (1, Associate::LeftAssociative)
+            Token::operator_priority(&Token::Operator(Operator::Sub)),
+            (5, Associate::LeftAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Mul)),
-            (2, Associate::LeftAssociative)
+            Token::operator_priority(&Token::Operator(Operator::Mul)),
+            (6, Associate::LeftAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Div)),
-            (2, Associate::LeftAssociative)
+            Token::operator_priority(&Token::Operator(Operator::Div)),
+            (6, Associate::LeftAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Pow)),
+            Token::operator_priority(&Token::Operator(Operator::Pow)),
+            (7, Associate::RightAssociative)
+        );
+        assert_eq!(
+            Token::operator_priority(&Token::Operator(Operator::Une)),
+            (8, Associate::RightAssociative)
+        );
+        assert_eq!(
+            Token::operator_priority(&Token::Operator(Operator::Fac)),
+            (9, Associate::LeftAssociative)
+        );
+        assert_eq!(
+            Token::operator_priority(&Token::Operator(Operator::Le)),
+            (4, Associate::LeftAssociative)
+        );
+        assert_eq!(
+            Token::operator_priority(&Token::Operator(Operator::Not)),
             (3, Associate::RightAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Une)),
-            (4, Associate::RightAssociative)
+            Token::operator_priority(&Token::Operator(Operator::And)),
+            (2, Associate::LeftAssociative)
         );
         assert_eq!(
-            Token::operator_priority(Token::Operator(Operator::Fac)),
-            (5, Associate::LeftAssociative)
+            Token::operator_priority(&Token::Operator(Operator::Or)),
+            (1, Associate::LeftAssociative)
         );
     }
+
+    #[test]
+    fn test_tokenize_logical_operators() {
+        for (t, op) in [("==", Operator::Eq), ("!=", Operator::Ne), ("≠", Operator::Ne), ("<", Operator::Lt), ("≤", Operator::Le), (">=", Operator::Ge), ("&&", Operator::And), ("||", Operator::Or), ("not", Operator::Not)] {
+            assert_eq!(Token::tokenize(t), Some(Token::Operator(op)));
+        }
+        assert_eq!(Token::tokenize("NOT"), Some(Token::Variable("NOT".into())));
+        assert_eq!(Token::tokenize("Not"), Some(Token::Variable("Not".into())));
+        assert_eq!(Number::from(true), Number::NaturalNumber(BigInt::one()));
+    }
 }
@@ -0,0 +1,151 @@
+use yarer::error::YarerError;
+use yarer::session::Session;
+
+/// A session in the f64 mode and one in the precision mode
+///
+fn sessions() -> [Session; 2] {
+    [Session::init(), Session::with_precision(30).unwrap()]
+}
+
+/// Resolves an expression in every session, and formats the result.
+/// Any panic makes the test fail.
+///
+fn resolve_in(sessions: &[Session], expr: &str) -> Vec<Result<String, YarerError>> {
+    sessions
+        .iter()
+        .map(|session| session.process(expr).resolve().map(|n| n.to_string()))
+        .collect()
+}
+
+fn resolve_all(expr: &str) -> Vec<Result<String, YarerError>> {
+    resolve_in(&sessions(), expr)
+}
+
+macro_rules! resolve_domain_err {
+    ($expr:expr) => {{
+        for res in resolve_all($expr) {
+            assert!(
+                matches!(res, Err(YarerError::DomainError { .. })),
+                "{} returned {:?}",
+                $expr,
+                res
+            );
+        }
+    }};
+}
+
+macro_rules! resolve_overflow_err {
+    ($expr:expr) => {{
+        for res in resolve_all($expr) {
+            assert!(
+                matches!(res, Err(YarerError::Overflow { .. })),
+                "{} returned {:?}",
+                $expr,
+                res
+            );
+        }
+    }};
+}
+
+#[test]
+fn test_domain_errors() {
+    resolve_domain_err!("sqrt(-1)");
+    resolve_domain_err!("ln(0)");
+    resolve_domain_err!("ln(-5)");
+    resolve_domain_err!("log(0)");
+    resolve_domain_err!("acos(2)");
+    resolve_domain_err!("asin(-1.5)");
+    resolve_domain_err!("(-8)^0.5");
+    resolve_domain_err!("(-1)!");
+    resolve_domain_err!("2.5!");
+}
+
+#[test]
+fn test_overflow_errors() {
+    resolve_overflow_err!("9^9^9");
+    resolve_overflow_err!("2^(2^64)");
+    resolve_overflow_err!("100000!");
+    resolve_overflow_err!("(2^64)!");
+    resolve_overflow_err!("exp(10^7)");
+    resolve_overflow_err!("1.5^(10^20)");
+}
+
+#[test]
+fn test_degenerate_powers() {
+    let session = Session::init();
+    assert_eq!(session.process("1^(10^30)").resolve().unwrap().to_string(), "1");
+    assert_eq!(session.process("(-1)^(10^30+1)").resolve().unwrap().to_string(), "-1");
+    assert_eq!(session.process("0^(10^30)").resolve().unwrap().to_string(), "0");
+    assert!(matches!(
+        session.process("0^-1").resolve(),
+        Err(YarerError::DivisionByZero { .. })
+    ));
+    assert!(matches!(
+        session.process("0^-0.5").resolve(),
+        Err(YarerError::DivisionByZero { .. })
+    ));
+}
+
+#[test]
+fn test_adversarial_expressions() {
+    let inputs = [
+        "", " ", "(", ")", "()", "((", "))", ")(", "+", "-", "*", "/", "^", "!", "=", ",", ";",
+        ";;", "1;", ";1", "1,2", ",1", "1,", "(1,2)", "1+", "+1", "*1", "1*", "1//2", "1**2",
+        "1^^2", "!1", "1!!", "=1", "1=", "x=", "=x", "x==1", "1=x", "sin", "sin(", "sin()",
+        "sin)", "sin(1,2)", "max", "max(", "max()", "max(1)", "max(,)", "min(1,2,3)", "(((1)))",
+        "1/(1-1)", "1/0.0", "x/x", "0/0", "tan(pi/2)", "cdf(10^400)", "pdf(-10^400)",
+        "exp(-10^400)", "sin(10^400)", "ln(10^400)", "sqrt(10^400)", "10^400/10^-400",
+        "(10^400)^0.5", "floor(-0.5)", "round(10^400+0.5)", "e^e^e^e", "2^-2^-2^-2", "---1",
+        "-(-(-1))!", "0!", "1.!", ".5", "5.", "1..2", "1.2.3", "$", "2 $ 3", "@", "#", "[1]",
+        "[(1])", "x = y = 3", "a; b; c", "pi = 3", "e = pi = 2", "x=1; x!", "-x^-x",
+    ];
+    let sessions = sessions();
+    for expr in inputs {
+        resolve_in(&sessions, expr);
+    }
+}
+
+#[test]
+fn test_random_token_soup() {
+    let pieces = [
+        "1", "0", "-", "+", "*", "/", "^", "!", "(", ")", ",", ";", "=", "x", "pi", "2.5",
+        "sin", "ln", "sqrt", "max", "acos", "exp", "10^400", "0.0",
+    ];
+    let sessions = sessions();
+    // a tiny linear congruential generator, to keep the test deterministic
+    let mut seed: u64 = 0x5eed;
+    for _ in 0..2_000 {
+        let mut expr = String::new();
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        let len = (seed >> 33) % 12;
+        for _ in 0..len {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            let index = usize::try_from((seed >> 33) % pieces.len() as u64).unwrap();
+            expr.push_str(pieces[index]);
+        }
+        resolve_in(&sessions, &expr);
+    }
+}
+
+#[test]
+fn test_deep_nesting() {
+    let sessions = sessions();
+    for expr in [
+        "-".repeat(10_000) + "1",
+        "not ".repeat(10_000) + "1",
+        "1".to_string() + &"!".repeat(10_000),
+        "1+".repeat(10_000) + "1",
+        "2^".repeat(10_000) + "1",
+        "sqrt(".repeat(10_000) + "1" + &")".repeat(10_000),
+    ] {
+        for res in resolve_in(&sessions, &expr) {
+            assert!(matches!(res, Err(YarerError::NestingLimit { .. })), "{res:?}");
+        }
+    }
+    // brackets alone don't nest the tree
+    let expr = "(".repeat(10_000) + "1" + &")".repeat(10_000);
+    assert_eq!(resolve_in(&sessions, &expr), [Ok("1".to_string()), Ok("1".to_string())]);
+    // below the limit the tree is evaluated
+    let expr = "-".repeat(200) + "1";
+    assert_eq!(resolve_in(&sessions, &expr), [Ok("1".to_string()), Ok("1".to_string())]);
+}
@@ -0,0 +1,103 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn yarer(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yarer"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn test_eval_arguments() {
+    let output = yarer(&["-e", "2^100"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "1267650600228229401496703205376\n");
+
+    let output = yarer(&["-e", "x = 2", "--eval", "x * 3"], "");
+    assert_eq!(stdout(&output), "2\n6\n");
+
+    let output = yarer(&["-e", "6 * 7", "-e", "ans + $1"], "");
+    assert_eq!(stdout(&output), "42\n84\n");
+
+    let output = yarer(&["-e", "6 * 7", "-e", ":clear", "-e", "1", "-e", "$1 + ans"], "");
+    assert_eq!(stdout(&output), "42\n1\n2\n");
+
+    // an expression can start with a minus, it's not taken for an option
+    let output = yarer(&["-e", "-2^2", "--eval", "-3!"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "4\n-6\n");
+
+    let output = yarer(&["-e", "1/0"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Divide by zero"));
+}
+
+#[test]
+fn test_stdin() {
+    let output = yarer(&[], "1+2\n\n# a comment\nhyp(a, b) = sqrt(a^2 + b^2)\nhyp(3, 4)\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "3\n5\n");
+
+    let output = yarer(&[], "1+2\n2 $ 3\n4+5\n");
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn test_script() {
+    let path = std::env::temp_dir().join(format!("yarer_script_{}.yr", std::process::id()));
+    std::fs::write(&path, "total := price * qty\nprice = 3; qty = 4\ntotal\n").unwrap();
+    let output = yarer(&[path.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "4\n12\n");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!yarer(&["no_such_script.yr"], "").status.success());
+}
+
+#[test]
+fn test_commands() {
+    let output = yarer(&["-e", ":rpn 3*4 + max(5, -6)"], "");
+    assert_eq!(stdout(&output), "3 4 * 5 6 neg max/2 +\n");
+
+    let output = yarer(&[], "x = 2\ny := x + 1\n:unset x\n:vars\n");
+    assert!(!stdout(&output).contains("x = "));
+    assert!(stdout(&output).contains("pi = 3.141592653589793 (decimal, constant)"));
+
+    let output = yarer(&[], "x = 2\n:reset\n:mode precision 30\n:mode\nx\n");
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "2\nprecision 30\nstrict\nreal\nimplicit\nauto\nungrouped\n");
+
+    let output = yarer(&["-e", ":mode precision 100000"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exceeds the maximum of 1000"));
+    assert!(!yarer(&["--precision", "100000", "-e", "1"], "").status.success());
+
+    let output = yarer(&["--explicit", "-e", "x = 2", "-e", "2x"], "");
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "2\n");
+    let output = yarer(&["-e", "x = 2", "-e", ":mode explicit", "-e", ":mode implicit", "-e", "3(x + 1)x"], "");
+    assert_eq!(stdout(&output), "2\n18\n");
+
+    let output = yarer(&["--complex", "-e", "sqrt(-9) * (1 + i)"], "");
+    assert_eq!(stdout(&output), "-3+3i\n");
+    let output = yarer(&[], ":mode complex\n:vars\n");
+    assert!(stdout(&output).contains("i = i (complex, constant)"));
+
+    let output = yarer(&["--format", "fixed:2", "--group", "-e", "1234567/3", "-e", ":mode sci 3", "-e", "ans"], "");
+    assert_eq!(stdout(&output), "411,522.33\n4.12e5\n");
+
+    let output = yarer(&["-e", ":unset x"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown variable(s) x"));
+}
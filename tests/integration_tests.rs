@@ -1,6 +1,8 @@
 use num::BigInt;
+use yarer::error::{Span, YarerError};
+use yarer::function::Arity;
 use yarer::rpn_resolver::*;
-use yarer::session::Session;
+use yarer::session::{Session, MAX_PRECISION};
 use yarer::token::*;
 
 macro_rules! resolve {
@@ -14,6 +16,13 @@ macro_rules! resolve {
     };
 }
 
+macro_rules! resolve_in {
+    ($session:expr, $expr:expr, $expected:expr) => {{
+        let mut resolver = $session.process($expr);
+        assert_eq!(resolver.resolve().unwrap(), $expected);
+    }};
+}
+
 macro_rules! resolve_decimal {
     ($expr:expr, $expected:expr) => {{
         let session = Session::init();
@@ -54,15 +63,15 @@ fn test_expressions() {
     resolve_decimal!("tau", std::f64::consts::TAU);
     resolve_decimal!("phi", (1.0 + 5.0f64.sqrt()) / 2.0);
     resolve_decimal!("gamma", 0.577_215_664_901_532_9_f64);
-    resolve_decimal!("3*2^3+6/(2+1)", 26.0);
+    resolve_natural!("3*2^3+6/(2+1)", 26);
     resolve_decimal!(
         "pi*4.+2^pi",
         std::f64::consts::PI * 4.0 + 2.0f64.powf(std::f64::consts::PI)
     );
     resolve_natural!("2^3 * 4 + 5^2", 8 * 4 + 25);
-    resolve_decimal!("sin(pi/4) + cos(pi/4)", 1.414213562373095); // Approximately sqrt(2)
+    resolve_decimal!("sin(pi/4) + cos(pi/4)", std::f64::consts::SQRT_2);
     resolve_decimal!("tan(pi/4) * cos(pi/6)", 0.8660254037844386); // Approximately sqrt(3)/2
-    resolve_decimal!("ln(e) + log10(100)", 1.0);
+    resolve_decimal!("ln(e) + log(100)", 3.0);
     //resolve_natural!("3 * 2^3! - 2 * 3 + 6 / (2 + 1)", 188);
     resolve_decimal!("cos(sin(0.5) * pi / 2)", 0.7295860397469262); // Approximately cos(PI/4)
     resolve_decimal!(
@@ -70,7 +79,7 @@ fn test_expressions() {
         8.0 * std::f64::consts::PI + std::f64::consts::PI / 2.0 - std::f64::consts::E
     );
     resolve_natural!("2 ^ 3 ^ 2", 512);
-    resolve_decimal!("ln(e^2) - log10(1000)", 2.);
+    resolve_decimal!("ln(e^2) - log(1000)", -1.);
     resolve_decimal!(
         "pi^2 - e^2",
         std::f64::consts::PI * std::f64::consts::PI - std::f64::consts::E * std::f64::consts::E
@@ -83,20 +92,20 @@ fn test_expressions() {
     resolve!("(2^3 + 3^2) * 4", Number::NaturalNumber(BigInt::from(68)));
     resolve_decimal!("e * pi - pi * e", 0.0);
     resolve_natural!("(2 + 3) * (4 - 5) + (6 - 7) * (8 + 9)", -22);
-    resolve_decimal!("ln(e^3) / log10(1000)", 3.);
+    resolve_natural!("ln(e^3) / log(1000)", 1);
     resolve_natural!("(2^2 + 3^2) * (4^2 + 5^2)", 533);
     resolve_decimal!(
         "pi*e*(pi-e)",
         std::f64::consts::PI * std::f64::consts::E * (std::f64::consts::PI - std::f64::consts::E)
     );
-    resolve_decimal!("((10 + 5) - 3 * ( 9 / 3 )) + 2", 8.0);
+    resolve_natural!("((10 + 5) - 3 * ( 9 / 3 )) + 2", 8);
     resolve_natural!("2^3^2 - 3^3", 512 - 27);
 
     resolve_decimal!("min(1,2)", 1.0);
     resolve_decimal!("max(1,2)", 2.0);
     resolve_decimal!("min(max(2,3),max(5,1))", 3.0);
 
-    resolve_decimal!("((2+3)!/5!)*(10-7)", 3.0);
+    resolve_natural!("((2+3)!/5!)*(10-7)", 3);
     resolve_decimal!("log(1000)+ln(e^3)", 6.0);
     resolve_decimal!("sqrt(9)+abs(-2)-min(5,3)", 2.0);
     resolve_decimal!("max(1+2,3*4)-min(10,5)", 7.0);
@@ -118,7 +127,7 @@ fn test_expressions() {
     resolve_decimal!("sqrt(2^3*4)", 5.656854249492381);
     resolve_natural!("2^(3! - 5)", 2);
     resolve_natural!("((3+1)!)+(2^3)", 32);
-    resolve_decimal!("((4+2)!)/((2+1)!)", 120.0);
+    resolve_natural!("((4+2)!)/((2+1)!)", 120);
     resolve_decimal!("cos(pi/3)^2+sin(pi/3)^2", 1.0);
     resolve_decimal!("atan(1)*4", std::f64::consts::PI);
     resolve_decimal!("acos(0)", std::f64::consts::FRAC_PI_2);
@@ -141,7 +150,7 @@ fn test_expressions() {
     resolve_decimal!("3^-3", 0.037037037037037035);
     resolve_natural!("2^(3^2)", 512);
     resolve_natural!("4!+3!+2!", 32);
-    resolve_decimal!("((2^3 + 4^2) / (5 - 3))", 12.0);
+    resolve_natural!("((2^3 + 4^2) / (5 - 3))", 12);
     resolve_decimal!("abs(-3)^2+abs(-4)^2", 25.0);
     resolve_decimal!("sqrt(2)^2", 2.0000000000000004);
     resolve_decimal!("sqrt(2)*sqrt(8)", 4.000000000000001);
@@ -195,7 +204,7 @@ fn test_sharing_session() {
     if let (Ok(a), Ok(b)) = (res.resolve(), res2.resolve()) {
         assert!(a == Number::NaturalNumber(BigInt::from(100)));
 
-        let b: i64 = b.into();
+        let b: i64 = b.try_into().unwrap();
         assert!(b == 3265920i64);
     }
 }
@@ -207,10 +216,37 @@ fn test_session_set() {
     let mut resolver: RpnResolver = session.process("x+2*3/(4-5)");
     assert_eq!(
         resolver.resolve().unwrap(),
-        Number::DecimalNumber(num_rational::BigRational::from_float(-2.0).unwrap())
+        Number::NaturalNumber(BigInt::from(-2))
     );
 }
 
+#[test]
+fn test_exact_division() {
+    resolve_natural!("6/3", 2);
+    resolve!(
+        "1/3*3",
+        Number::DecimalNumber(num_rational::BigRational::from_integer(BigInt::from(1)))
+    );
+    resolve_natural!("(10^30*7)/7", BigInt::from(10).pow(30));
+    resolve!(
+        "10^30/7",
+        Number::DecimalNumber(num_rational::BigRational::new(
+            BigInt::from(10).pow(30),
+            BigInt::from(7)
+        ))
+    );
+    resolve!(
+        "1/3",
+        Number::DecimalNumber(num_rational::BigRational::new(
+            BigInt::from(1),
+            BigInt::from(3)
+        ))
+    );
+    resolve_natural!("1.5/0.5", 3);
+    resolve_err!("1/0");
+    resolve_err!("1/0.0");
+}
+
 #[test]
 fn test_factorial_invalid_operand() {
     let session = Session::init();
@@ -234,3 +270,643 @@ fn test_chained_without_assignment() {
     let mut resolver = session.process("1+2; 3+4");
     assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(7)));
 }
+
+#[test]
+fn test_precision_mode() {
+    let session = Session::with_precision(60).unwrap();
+
+    let mut resolver = session.process("pi");
+    assert_eq!(
+        resolver.resolve().unwrap().to_precise_string(50),
+        "3.1415926535897932384626433832795028841971693993751"
+    );
+
+    let mut resolver = session.process("sqrt(2)^2");
+    assert_eq!(resolver.resolve().unwrap().to_precise_string(50), "2");
+
+    let mut resolver = session.process("2^0.5");
+    assert_eq!(
+        resolver.resolve().unwrap().to_precise_string(40),
+        "1.41421356237309504880168872420969807857"
+    );
+
+    let mut resolver = session.process("ln(e^3) + log(1000)");
+    assert_eq!(resolver.resolve().unwrap().to_precise_string(50), "6");
+
+    let mut resolver = session.process("sin(pi/6) + cos(pi/3) + tan(pi/4)");
+    assert_eq!(resolver.resolve().unwrap().to_precise_string(50), "2");
+
+    let mut resolver = session.process("asin(1) + acos(1) + atan(1) * 2");
+    let mut pi = session.process("pi");
+    assert_eq!(
+        resolver.resolve().unwrap().to_precise_string(50),
+        pi.resolve().unwrap().to_precise_string(50)
+    );
+
+    let mut resolver = session.process("exp(1) - e");
+    assert_eq!(resolver.resolve().unwrap().to_precise_string(50), "0");
+
+    let mut resolver = session.process("cdf(0) + pdf(0)");
+    assert_eq!(
+        resolver.resolve().unwrap().to_precise_string(30),
+        "0.898942280401432677939946059934"
+    );
+
+    let mut resolver = session.process("2^-2");
+    assert_eq!(
+        resolver.resolve().unwrap(),
+        Number::DecimalNumber(num_rational::BigRational::new(BigInt::from(1), BigInt::from(4)))
+    );
+
+    let mut resolver = session.process("ln(0)");
+    assert!(resolver.resolve().is_err());
+    let mut resolver = session.process("sqrt(-1)");
+    assert!(resolver.resolve().is_err());
+    let mut resolver = session.process("(-8)^0.5");
+    assert!(resolver.resolve().is_err());
+
+    // the constants reassigned by the user survive a change of precision or mode
+    session.process("pi = 3").resolve().unwrap();
+    session.set("i", 5);
+    session.set_precision(Some(20)).unwrap();
+    session.set_complex(true);
+    resolve_in!(session, "pi + i", Number::NaturalNumber(BigInt::from(8)));
+    assert_eq!(session.process("tau").resolve().unwrap().to_precise_string(19), "6.283185307179586477");
+    session.set_complex(false);
+    session.set_precision(None).unwrap();
+    resolve_in!(session, "pi + i", Number::NaturalNumber(BigInt::from(8)));
+    assert_eq!(f64::from(session.process("tau").resolve().unwrap()), std::f64::consts::TAU);
+
+    // a precision above the maximum is rejected and leaves the mode unchanged
+    assert!(matches!(
+        session.set_precision(Some(MAX_PRECISION + 1)),
+        Err(YarerError::PrecisionLimit { digits: 1001, max: 1000, .. })
+    ));
+    assert_eq!(session.precision(), None);
+    assert!(Session::with_precision(u64::MAX).is_err());
+}
+
+#[test]
+fn test_error_kinds() {
+    let session = Session::init();
+
+    let expr = "y = 1; x = 10 / (y - y)";
+    match session.process(expr).resolve() {
+        Err(YarerError::DivisionByZero { span }) => {
+            assert_eq!(&expr[span.start..span.end], "/");
+        }
+        other => panic!("Expected a division by zero, got {other:?}"),
+    }
+
+    let err = session.process("min()").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::ArityMismatch { expected: Arity::AtLeast(1), found: 0, .. }));
+    assert_eq!(err.span(), Span::new(0, 3));
+
+    let err = session.process("1 + (2").resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnbalancedBracket { span: Span::new(4, 5) });
+}
+
+#[test]
+fn test_strict_mode() {
+    let session = Session::init();
+    assert!(session.is_strict());
+
+    let expr = "pirce * qty";
+    match session.process(expr).resolve() {
+        Err(YarerError::UnknownVariable { name, span }) => {
+            assert_eq!(name, "pirce");
+            assert_eq!(&expr[span.start..span.end], "pirce");
+        }
+        other => panic!("Expected an unknown variable, got {other:?}"),
+    }
+
+    // assignment targets don't need to be defined
+    let mut resolver = session.process("price = 2; qty = 3; price * qty");
+    assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(6)));
+    let mut resolver = session.process("a = b = 4");
+    assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(4)));
+
+    // but they can't be read in their own definition
+    let err = session.process("z = z + 1").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::UnknownVariable { name, .. } if name == "z"));
+    let err = session.process("sin(w)").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::UnknownVariable { name, .. } if name == "w"));
+    // nor before, even if the value is used after the assignment
+    let err = session.process("v + (v = 1)").resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnknownVariable { name: "v".to_string(), span: Span::new(0, 1) });
+    let err = session.process("max(u, u = 1)").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::UnknownVariable { name, .. } if name == "u"));
+
+    session.set_strict(false);
+    let mut resolver = session.process("pirce * 3 + 1");
+    assert_eq!(resolver.resolve().unwrap(), Number::NaturalNumber(BigInt::from(1)));
+}
+
+#[test]
+fn test_parse_errors() {
+    let session = Session::init();
+
+    let expr = "2 $ 3 @ 4";
+    let mut resolver = session.process(expr);
+    assert_eq!(
+        resolver.parse_errors(),
+        &[
+            YarerError::UnknownCharacter { character: '$', span: Span::new(2, 3) },
+            YarerError::UnknownCharacter { character: '@', span: Span::new(6, 7) },
+        ]
+    );
+    assert_eq!(resolver.resolve().unwrap_err(), resolver.parse_errors()[0]);
+
+    let err = session.process("(1 + 2))").resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnbalancedBracket { span: Span::new(7, 8) });
+    let err = session.process("((1 + 2)").resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnbalancedBracket { span: Span::new(0, 1) });
+
+    assert!(session.process("max(1, 2) * [3 + 4]").parse_errors().is_empty());
+}
+
+#[test]
+fn test_user_functions() {
+    let session = Session::init();
+    session.define_function("hyp", &["a", "b"], "sqrt(a^2 + b^2)").unwrap();
+    session.define_function("sq", &["x"], "x * x").unwrap();
+    session.define_function("norm2", &["x", "y"], "sq(x) + sq(y)").unwrap();
+    session.define_function("answer", &[], "42").unwrap();
+
+    assert_eq!(session.process("hyp(3, 4)").resolve().unwrap().to_string(), "5");
+    resolve_in!(session, "2 * norm2(1, 2) + 1", Number::NaturalNumber(BigInt::from(11)));
+    resolve_in!(session, "answer() - 2", Number::NaturalNumber(BigInt::from(40)));
+    resolve_in!(session, "sq(sq(2))", Number::NaturalNumber(BigInt::from(16)));
+
+    // the parameters don't clobber the session variables
+    session.set("x", 7);
+    resolve_in!(session, "sq(3) + x", Number::NaturalNumber(BigInt::from(16)));
+    let err = session.process("hyp(3, 4) + a").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::UnknownVariable { name, .. } if name == "a"));
+
+    // the variables assigned in the body are local to the call, the others are read from the session
+    session.define_function("shift", &["x"], "y = x + k; y * 2").unwrap();
+    session.set("y", 1);
+    session.set("k", 10);
+    resolve_in!(session, "shift(1) + y", Number::NaturalNumber(BigInt::from(23)));
+    session.set("k", 20);
+    resolve_in!(session, "shift(1)", Number::NaturalNumber(BigInt::from(42)));
+    assert_eq!(session.function("shift").map(|f| f.arity()), Some(Arity::Exact(1)));
+
+    // a name followed by a bracket is a call only if it's a function of the session
+    let expr = "1 + nope(2)";
+    let err = session.process(expr).resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnknownVariable { name: "nope".to_string(), span: Span::new(4, 8) });
+    session.set("z", 2);
+    resolve_in!(session, "z (1+2)", Number::NaturalNumber(BigInt::from(6)));
+    resolve_in!(session, "z(1+2)^2", Number::NaturalNumber(BigInt::from(18)));
+    session.set_implicit(false);
+    let err = session.process(expr).resolve().unwrap_err();
+    assert_eq!(err, YarerError::UnknownFunction { name: "nope".to_string(), span: Span::new(4, 8) });
+    session.set_implicit(true);
+    let err = session.process("hyp(1)").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::ArityMismatch { expected: Arity::Exact(2), found: 1, .. }));
+
+    // errors in the body are reported at the call
+    session.define_function("inv", &["x"], "1 / x").unwrap();
+    let err = session.process("2 * inv(0)").resolve().unwrap_err();
+    assert_eq!(err, YarerError::DivisionByZero { span: Span::new(4, 7) });
+
+    // 'g' must be a function when 'f' is defined, not to be read as 'g * (x)'
+    session.define_function("g", &["x"], "x").unwrap();
+    session.define_function("f", &["x"], "g(x) + 1").unwrap();
+    session.define_function("g", &["x"], "f(x) - 1").unwrap();
+    let err = session.process("f(1)").resolve().unwrap_err();
+    assert_eq!(err, YarerError::RecursionLimit { function: "f".to_string(), span: Span::new(0, 1) });
+
+    assert!(matches!(
+        session.define_function("sin", &["x"], "x"),
+        Err(YarerError::InvalidDefinition { .. })
+    ));
+    assert!(matches!(
+        session.define_function("k", &["x", "x"], "x"),
+        Err(YarerError::InvalidDefinition { .. })
+    ));
+    assert!(matches!(
+        session.define_function("k", &["x"], "x $ 2"),
+        Err(YarerError::UnknownCharacter { .. })
+    ));
+}
+
+#[test]
+fn test_native_functions() {
+    let session = Session::init();
+    session
+        .register_function("tax", Arity::Exact(2), |args: &[Number]| {
+            let region = i32::try_from(args[1].clone())?;
+            let rate = match region {
+                1 => 20,
+                2 => 10,
+                _ => {
+                    return Err(YarerError::FunctionError {
+                        function: "tax".to_string(),
+                        message: format!("unknown region {region}"),
+                        span: Span::default(),
+                    })
+                }
+            };
+            Ok(args[0].clone() * Number::NaturalNumber(BigInt::from(rate)) / Number::NaturalNumber(BigInt::from(100)))
+        })
+        .unwrap();
+    session
+        .register_function("count", Arity::AtLeast(0), |args: &[Number]| {
+            Ok(Number::NaturalNumber(BigInt::from(args.len())))
+        })
+        .unwrap();
+
+    resolve_in!(session, "100 + tax(100, 1)", Number::NaturalNumber(BigInt::from(120)));
+    resolve_in!(session, "count()", Number::NaturalNumber(BigInt::from(0)));
+    resolve_in!(session, "count(1, (2 + 3), max(4, 5)) - 1", Number::NaturalNumber(BigInt::from(2)));
+    resolve_in!(session, "count(count(1, 2), 3)", Number::NaturalNumber(BigInt::from(2)));
+
+    // user functions can call native functions
+    session.define_function("gross", &["x"], "x + tax(x, 2)").unwrap();
+    resolve_in!(session, "gross(50)", Number::NaturalNumber(BigInt::from(55)));
+
+    let expr = "2 * tax(10, 3)";
+    let err = session.process(expr).resolve().unwrap_err();
+    assert_eq!(err.span(), Span::new(4, 7));
+    assert_eq!(err.to_string(), "Runtime error: Function tax failed: unknown region 3.");
+
+    let err = session.process("tax(10, 1, 2)").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::ArityMismatch { expected: Arity::Exact(2), found: 3, .. }));
+
+    assert!(matches!(
+        session.register_function("sqrt", Arity::Exact(1), |args: &[Number]| Ok(args[0].clone())),
+        Err(YarerError::InvalidDefinition { .. })
+    ));
+}
+
+#[test]
+fn test_variadic_functions() {
+    resolve_decimal!("max(1, 2, 3)", 3.0);
+    resolve_decimal!("max(3, 2, 1)", 3.0);
+    resolve_decimal!("min(4, -2, 8, 0)", -2.0);
+    resolve_decimal!("max(5)", 5.0);
+    resolve_decimal!("sum(1, 2, 3, 4)", 10.0);
+    resolve_decimal!("avg(1, 2, 3, 4)", 2.5);
+    resolve_decimal!("hypot(3, 4)", 5.0);
+    resolve_decimal!("hypot(1, 2, 2)", 3.0);
+    resolve_decimal!("sum(max(1, 2, 3), min(4, 5), 6) * 2", 26.0);
+    resolve_decimal!("avg(sin(0), (1 + 2), 2^3, 1)", 3.0);
+    resolve_decimal!("-sum(1, 2) + 1", -2.0);
+
+    // exact arithmetic, even for fractions
+    resolve!("avg(1, 2) * 2", Number::DecimalNumber(num::BigRational::from_integer(BigInt::from(3))));
+    resolve!("sum(1/3, 1/6) * 6", Number::DecimalNumber(num::BigRational::from_integer(BigInt::from(3))));
+
+    let session = Session::init();
+    let err = session.process("1 + sin(1, 2)").resolve().unwrap_err();
+    assert_eq!(
+        err,
+        YarerError::ArityMismatch {
+            function: "sin".to_string(),
+            expected: Arity::Exact(1),
+            found: 2,
+            span: Span::new(4, 7)
+        }
+    );
+    let err = session.process("avg()").resolve().unwrap_err();
+    assert!(matches!(err, YarerError::ArityMismatch { expected: Arity::AtLeast(1), found: 0, .. }));
+
+    let session = Session::with_precision(40).unwrap();
+    let res = session.process("hypot(1, 1)").resolve().unwrap();
+    assert!(res.to_precise_string(40).starts_with("1.4142135623730950488016887242096980785"));
+}
+
+#[test]
+fn test_compiled_expression() {
+    use std::sync::{Arc, LazyLock, Mutex};
+    use yarer::compiled::CompiledExpression;
+
+    // a global cache of compiled formulas
+    static CACHE: LazyLock<Mutex<Vec<Arc<CompiledExpression>>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+    CACHE.lock().unwrap().push(Arc::new(CompiledExpression::compile("x * (x + 1) / 2").unwrap()));
+
+    let handles: Vec<_> = (1..=4)
+        .map(|n| {
+            let formula = Arc::clone(&CACHE.lock().unwrap()[0]);
+            std::thread::spawn(move || {
+                let session = Session::init();
+                session.set("x", n * 10);
+                formula.resolve(&session).unwrap()
+            })
+        })
+        .collect();
+    let results: Vec<Number> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(results, [55, 210, 465, 820].map(|n| Number::NaturalNumber(BigInt::from(n))));
+}
+
+#[test]
+fn test_expression_tree() {
+    use yarer::ast::{fold, Expr, Fold};
+    use yarer::compiled::CompiledExpression;
+    use yarer::parser::Parser;
+
+    /// Replaces every 'x * 1' and '1 * x' with 'x'
+    struct Simplify;
+
+    impl Fold for Simplify {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            let one = Number::NaturalNumber(BigInt::from(1));
+            match fold(self, expr) {
+                Expr::Binary { op: Operator::Mul, left, right, .. } => match (*left, *right) {
+                    (Expr::Number { value, .. }, other) | (other, Expr::Number { value, .. })
+                        if value == one =>
+                    {
+                        other
+                    }
+                    (left, right) => Expr::Binary {
+                        op: Operator::Mul,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        span: Span::default(),
+                    },
+                },
+                other => other,
+            }
+        }
+    }
+
+    let expr = Parser::parse_expr("1 * (x * 1 + 2) * y").unwrap();
+    let simplified = CompiledExpression::from_expr(Simplify.fold_expr(expr));
+    assert_eq!(simplified.to_string(), "(x + 2) * y");
+
+    let session = Session::init();
+    session.set("x", 3);
+    session.set("y", 4);
+    assert_eq!(simplified.resolve(&session).unwrap(), Number::NaturalNumber(BigInt::from(20)));
+
+    // a trailing semicolon ends the last statement
+    resolve_in!(session, "z = 5;", Number::NaturalNumber(BigInt::from(5)));
+}
+
+#[test]
+fn test_formula_cells() {
+    let session = Session::init();
+    let total = || -> f64 { session.process("total").resolve().unwrap().into() };
+    session.bind("net", "price * qty").unwrap();
+    session.bind("total", "net * (1 + vat)").unwrap();
+    assert!(session.process("total").resolve().is_err());
+
+    session.set("price", 10);
+    session.set("qty", 3);
+    session.setf("vat", 0.5).unwrap();
+    assert_eq!(total(), 45.0);
+
+    session.process("qty = 4").resolve().unwrap();
+    assert_eq!(total(), 60.0);
+    assert_eq!(session.dependents("qty"), ["net", "total"]);
+    assert_eq!(session.dependency_graph()["total"].iter().collect::<Vec<_>>(), ["net", "vat"]);
+
+    let err = session.bind("price", "total / 2").unwrap_err();
+    assert_eq!(
+        err,
+        YarerError::CyclicDefinition {
+            cycle: vec!["price".into(), "total".into(), "net".into(), "price".into()],
+            span: Span::default()
+        }
+    );
+    assert_eq!(err.to_string(), "Runtime error: Cyclic definition price -> total -> net -> price.");
+    assert!(matches!(session.bind("x", "x + 1"), Err(YarerError::CyclicDefinition { .. })));
+
+    // a value replaces the formula
+    session.set("net", 100);
+    session.set("price", 1);
+    assert_eq!(total(), 150.0);
+    assert!(session.cell("net").is_none());
+
+    // the cells are recomputed when the functions they call are redefined
+    session.define_function("dbl", &["x"], "2 * x").unwrap();
+    session.define_function("quad", &["x"], "dbl(dbl(x))").unwrap();
+    session.bind("d", "dbl(q)").unwrap();
+    session.bind("h", "quad(q)").unwrap();
+    session.set("q", 5);
+    let value = |name: &str| session.process(name).resolve().unwrap().to_string();
+    assert_eq!((value("d"), value("h")), ("10".to_string(), "20".to_string()));
+    session.define_function("dbl", &["x"], "3 * x").unwrap();
+    assert_eq!((value("d"), value("h")), ("15".to_string(), "45".to_string()));
+    session.register_function("dbl", Arity::Exact(1), |_| Ok(Number::NaturalNumber(BigInt::from(0)))).unwrap();
+    assert_eq!((value("d"), value("h")), ("0".to_string(), "0".to_string()));
+
+    // and when a variable read by the body of a function they call changes
+    session.define_function("f", &["x"], "x + k").unwrap();
+    session.define_function("g", &["x"], "f(x) * 2").unwrap();
+    session.set("k", 1);
+    session.bind("s", "f(q)").unwrap();
+    session.bind("t", "g(q)").unwrap();
+    assert_eq!((value("s"), value("t")), ("6".to_string(), "12".to_string()));
+    session.set("k", 10);
+    assert_eq!((value("s"), value("t")), ("15".to_string(), "30".to_string()));
+    assert_eq!(session.dependents("k"), ["s", "t"]);
+    session.define_function("f", &["x"], "x + m").unwrap();
+    session.set("m", 100);
+    assert_eq!((value("s"), value("t")), ("105".to_string(), "210".to_string()));
+    assert!(matches!(
+        session.define_function("f", &["x"], "x + s"),
+        Err(YarerError::CyclicDefinition { .. })
+    ));
+    assert_eq!(session.process("f(0)").resolve().unwrap().to_string(), "100");
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir().join(format!("yarer_session_{}.txt", std::process::id()));
+
+    let session = Session::init();
+    session.process("third = 1/3; big = 3^200; e = 3").resolve().unwrap();
+    session.setf("two", 2.0).unwrap();
+    session.define_function("hyp", &["a", "b"], "sqrt(a^2 + b^2)").unwrap();
+    session.define_function("sq", &["x"], "x * x").unwrap();
+    session.define_function("norm2", &["x", "y"], "sq(x) + sq(y)").unwrap();
+    session.bind("total", "third * big").unwrap();
+    session.save(&path).unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.contains("third = 1/3\n"));
+    assert!(text.contains("two = 2/1\n"));
+    assert!(text.contains("e = 3\n"));
+    assert!(!text.contains("pi ="));
+    assert!(text.contains("hyp(a, b) = sqrt(a^2 + b^2)\n"));
+    assert!(text.contains("total := third * big\n"));
+
+    let restored = Session::init();
+    restored.load(&path).unwrap();
+    for name in ["third", "big", "two", "e", "total", "pi"] {
+        assert_eq!(restored.process(name).resolve(), session.process(name).resolve());
+    }
+    resolve_in!(restored, "hyp(3, 4)", Number::DecimalNumber(num_rational::BigRational::from_integer(BigInt::from(5))));
+    // 'norm2' is loaded before 'sq', and still calls it
+    resolve_in!(restored, "norm2(1, 2)", Number::NaturalNumber(BigInt::from(5)));
+    restored.set("big", 3);
+    resolve_in!(restored, "total", Number::DecimalNumber(num_rational::BigRational::from_integer(BigInt::from(1))));
+
+    std::fs::write(&path, "x = 1\ny = oops\n").unwrap();
+    let err = Session::init().load(&path).unwrap_err();
+    assert_eq!(err.to_string(), "line 2: invalid entry 'y = oops'");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_results() {
+    let session = Session::init();
+    let recorded = |expr: &str| session.process_recorded(expr).resolve();
+    assert!(matches!(recorded("ans"), Err(YarerError::UnknownVariable { .. })));
+    assert!(matches!(recorded("$1"), Err(YarerError::UnknownVariable { .. })));
+
+    assert_eq!(recorded("1/3"), Ok(Number::DecimalNumber(num_rational::BigRational::new(1.into(), 3.into()))));
+    assert_eq!(recorded("x = 2^100"), Ok(Number::NaturalNumber(BigInt::from(2).pow(100u32))));
+    assert!(recorded("1/0").is_err());
+    assert_eq!(recorded("ans * 3 - $2 * 3"), Ok(Number::NaturalNumber(BigInt::from(0))));
+    let one = Number::DecimalNumber(num_rational::BigRational::from_integer(BigInt::from(1)));
+    assert_eq!(recorded("$1 * 3"), Ok(one.clone()));
+    assert_eq!(recorded("ans + $3"), Ok(one.clone()));
+
+    assert_eq!(session.results().len(), 5);
+    assert_eq!(session.result(2), Some(Number::NaturalNumber(BigInt::from(2).pow(100u32))));
+    assert_eq!(session.result(0), None);
+    assert!(matches!(recorded("$2 = 1"), Err(YarerError::InvalidAssignment { .. })));
+
+    // neither a processed nor a compiled expression records its result, but both can read them
+    resolve_in!(session, "$2 / 2^99", Number::NaturalNumber(BigInt::from(2)));
+    yarer::compiled::CompiledExpression::compile("42").unwrap().resolve(&session).unwrap();
+    assert_eq!(session.results().len(), 5);
+    assert_eq!(session.variables().get("ans"), Some(&one));
+
+    session.clear_results();
+    assert!(session.results().is_empty());
+    assert!(matches!(session.process("$1").resolve(), Err(YarerError::UnknownVariable { .. })));
+    assert_eq!(recorded("7"), Ok(Number::NaturalNumber(BigInt::from(7))));
+    assert_eq!(session.result(1), Some(Number::NaturalNumber(BigInt::from(7))));
+
+    session.reset();
+    assert!(session.results().is_empty());
+}
+
+#[test]
+fn test_number_literals() {
+    resolve_natural!("0xFF + 0b1010 - 0o17", 250);
+    resolve_natural!("1_000_000 * 2", 2_000_000);
+    resolve!("0xFFFF_FFFF_FFFF_FFFF + 1", Number::NaturalNumber(BigInt::from(2).pow(64)));
+    resolve_decimal!("6.022e23 / 1e23", 6.022);
+    resolve_decimal!("2.5E+3 + .5e-1", 2500.05);
+    resolve_natural!("1e400 / 1e399", 10);
+    // the decimals are exact, not the closest f64
+    resolve_natural!("0.1 + 0.2 == 0.3", 1);
+    resolve_natural!("1e308 * 10 == 1e309", 1);
+    resolve!("0.1 * 3", Number::DecimalNumber(num_rational::BigRational::new(3.into(), 10.into())));
+    let err = Session::init().process("2 * 1e99999999").resolve().unwrap_err();
+    assert_eq!(err, YarerError::Overflow { span: Span::new(4, 14) });
+}
+
+#[test]
+fn test_unicode() {
+    resolve_natural!("(7 − 3) × 5 ÷ 2 · 3", 30);
+    resolve_natural!("−2³ + 10²", 92);
+    resolve_decimal!("2⁻¹", 0.5);
+    resolve_decimal!("√16 + √(3² + 4²)", 9.0);
+    // a superscript is a '^' like any other, so the negation binds first as in '-2^2'
+    resolve_natural!("-2²", 4);
+    resolve_natural!("−2² + 2³!", 68);
+    let session = Session::init();
+    session.set("x", 2);
+    resolve_in!(session, "-x²", Number::NaturalNumber(BigInt::from(4)));
+    resolve_in!(session, "-x^2", Number::NaturalNumber(BigInt::from(4)));
+    resolve_decimal!("π − pi + τ / 2 − φ", std::f64::consts::PI - 1.618_033_988_749_895);
+    resolve_err!("2⁻");
+}
+
+#[test]
+fn test_implicit_multiplication() {
+    let session = Session::init();
+    session.set("x", 3);
+    session.set("a", 5);
+    session.set("b", 2);
+    resolve_in!(session, "2x^2 + 3(x - 1)", Number::NaturalNumber(BigInt::from(24)));
+    resolve_in!(session, "(a + b)(a - b)", Number::NaturalNumber(BigInt::from(21)));
+    resolve_in!(session, "x!2", Number::NaturalNumber(BigInt::from(12)));
+    let res: f64 = session.process("2pi").resolve().unwrap().into();
+    assert!((res - std::f64::consts::TAU).abs() < 1e-10);
+    resolve_err!("1 2");
+
+    session.set_implicit(false);
+    assert!(!session.is_implicit());
+    assert!(matches!(session.process("2x").resolve(), Err(YarerError::UnexpectedToken { .. })));
+    resolve_in!(session, "2*x", Number::NaturalNumber(BigInt::from(6)));
+}
+
+#[test]
+fn test_logical_operators() {
+    let session = Session::init();
+    session.process("age = 20; income = 5000; rent = 1200").resolve().unwrap();
+    resolve_in!(session, "age >= 18 && income > 3 * rent", Number::from(true));
+    resolve_in!(session, "age < 18 || not income > 3 * rent", Number::from(false));
+    resolve_in!(session, "(age != 20) + (1 == 1.0) + (2 ≤ 2) + (0.5 > 1/3)", Number::NaturalNumber(BigInt::from(3)));
+    resolve_in!(session, "ok = income / rent >= 4; ok * 100", Number::NaturalNumber(BigInt::from(100)));
+    resolve_in!(session, "not 0 && -2", Number::from(true));
+
+    session.set_complex(true);
+    resolve_in!(session, "i * i == -1", Number::from(true));
+    assert!(matches!(session.process("i < 1").resolve(), Err(YarerError::NotOrdered { .. })));
+}
+
+#[test]
+fn test_complex() {
+    use num::complex::Complex;
+    let rational = |n: i32| num_rational::BigRational::from_integer(BigInt::from(n));
+    let complex = |re: i32, im: i32| Number::ComplexNumber(Complex::new(rational(re), rational(im)));
+
+    // the real sessions don't know 'i', nor the complex roots
+    resolve_err!("i * i");
+    assert!(matches!(Session::init().process("sqrt(-4)").resolve(), Err(YarerError::DomainError { .. })));
+
+    let session = Session::init();
+    session.set_complex(true);
+    assert!(session.is_complex());
+    resolve_in!(session, "i * i", Number::NaturalNumber(BigInt::from(-1)));
+    resolve_in!(session, "(1 + 2*i) * (3 - i)", complex(5, 5));
+    resolve_in!(session, "(1 + i)^-2", Number::ComplexNumber(Complex::new(rational(0), num_rational::BigRational::new((-1).into(), 2.into()))));
+    resolve_in!(session, "sqrt(-4)", Number::ComplexNumber(Complex::new(rational(0), rational(2))));
+    resolve_in!(session, "abs(3 + 4*i)", Number::DecimalNumber(rational(5)));
+    resolve_in!(session, "round(1.6 + 2.4*i)", complex(2, 2));
+    resolve_in!(session, "sum(i, 1, 2)", complex(3, 1));
+
+    let root: Complex<f64> = session.process("(-8)^(1/3)").resolve().unwrap().into();
+    assert!((root - Complex::new(1.0, 3f64.sqrt())).norm() < 1e-10);
+    let euler: Complex<f64> = session.process("exp(i * pi)").resolve().unwrap().into();
+    assert!((euler + 1.0).norm() < 1e-10);
+    assert_eq!(session.process("ln(-1)").resolve().unwrap().to_string(), "3.141592653589793i");
+
+    assert!(matches!(session.process("max(i, 1)").resolve(), Err(YarerError::NotOrdered { .. })));
+    assert!(matches!(session.process("i!").resolve(), Err(YarerError::DomainError { .. })));
+    assert!(session.process("i").resolve().unwrap().partial_cmp(&Number::NaturalNumber(BigInt::from(1))).is_none());
+
+    // 'i' is a constant of the session, like pi, not a free variable
+    session.bind("w", "x * i + pi").unwrap();
+    let deps = session.cell("w").unwrap().formula.dependencies_with(session.is_complex());
+    assert_eq!(deps.variables.into_iter().collect::<Vec<_>>(), ["x"]);
+    assert_eq!(deps.constants.into_iter().collect::<Vec<_>>(), ["i", "pi"]);
+    session.set("x", 2);
+    assert_eq!(session.process("w - pi").resolve().unwrap(), complex(0, 2));
+
+    // the complex values are saved exactly, but 'i' isn't
+    let path = std::env::temp_dir().join(format!("yarer_complex_{}.txt", std::process::id()));
+    session.process("z = 1/2 - 3*i").resolve().unwrap();
+    session.save(&path).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.contains("z = 1/2-3i\n"));
+    assert!(!text.contains("i = "));
+    let restored = Session::init();
+    restored.load(&path).unwrap();
+    assert_eq!(restored.process("z").resolve(), session.process("z").resolve());
+    std::fs::remove_file(&path).unwrap();
+
+    session.set_complex(false);
+    assert!(session.process("i").resolve().is_err());
+}
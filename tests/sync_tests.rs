@@ -0,0 +1,78 @@
+#![cfg(feature = "sync")]
+
+use std::{sync::Arc, thread};
+
+use yarer::{function::Arity, rpn_resolver::RpnResolver, session::Session, token::Number};
+
+// fails to compile if the session or its resolvers can't be shared between threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Session>();
+    assert_send_sync::<RpnResolver<'static>>();
+};
+
+#[test]
+fn test_concurrent_updates() {
+    let session = Arc::new(Session::init());
+    session.set("a", 0);
+
+    let writers: Vec<_> = (0..8)
+        .map(|_| {
+            let session = Arc::clone(&session);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    session.process("a = a + 1").resolve().unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in writers {
+        handle.join().unwrap();
+    }
+    // no update is lost between the read and the write of 'a'
+    assert_eq!(session.process("a").resolve().unwrap().to_string(), "800");
+}
+
+#[test]
+fn test_shared_session() {
+    let session = Arc::new(Session::init());
+    session.set("x", 10);
+    session
+        .register_function("twice", Arity::Exact(1), |args: &[Number]| {
+            Ok(args[0].clone() + args[0].clone())
+        })
+        .unwrap();
+
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let session = Arc::clone(&session);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let result = session.process("twice(x) + 1").resolve().unwrap();
+                    assert_eq!(result.to_string(), "21");
+                }
+            })
+        })
+        .collect();
+
+    let writers: Vec<_> = (0..4)
+        .map(|i| {
+            let session = Arc::clone(&session);
+            thread::spawn(move || {
+                let name = format!("y{i}");
+                for n in 0..100 {
+                    session.process(&format!("{name} = {n}")).resolve().unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in readers.into_iter().chain(writers) {
+        handle.join().unwrap();
+    }
+    for i in 0..4 {
+        let result = session.process(&format!("y{i}")).resolve().unwrap();
+        assert_eq!(result.to_string(), "99");
+    }
+}